@@ -64,6 +64,50 @@ impl AABB3 {
             && (point.z >= self.min.z && point.z < self.max.z)
     }
 
+    pub fn intersects(&self, other: &Self) -> bool {
+        (self.min.x <= other.max.x && other.min.x <= self.max.x)
+            && (self.min.y <= other.max.y && other.min.y <= self.max.y)
+            && (self.min.z <= other.max.z && other.min.z <= self.max.z)
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        let d = self.max - self.min;
+        if d.x < 0.0 || d.y < 0.0 || d.z < 0.0 {
+            return 0.0;
+        }
+        2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
+
+    pub fn corners(&self) -> [Vector3<f32>; 8] {
+        let Self { min, max } = *self;
+        [
+            Vector3::from([min.x, min.y, min.z]),
+            Vector3::from([max.x, min.y, min.z]),
+            Vector3::from([min.x, max.y, min.z]),
+            Vector3::from([max.x, max.y, min.z]),
+            Vector3::from([min.x, min.y, max.z]),
+            Vector3::from([max.x, min.y, max.z]),
+            Vector3::from([min.x, max.y, max.z]),
+            Vector3::from([max.x, max.y, max.z]),
+        ]
+    }
+
+    /// Squared distance from the point to the box, zero when the point is inside.
+    pub fn distance2_to_point(&self, point: Vector3<f32>) -> f32 {
+        let mut d2 = 0.0;
+        for axis in 0..3 {
+            let d = if point[axis] < self.min[axis] {
+                self.min[axis] - point[axis]
+            } else if point[axis] > self.max[axis] {
+                point[axis] - self.max[axis]
+            } else {
+                0.0
+            };
+            d2 += d * d;
+        }
+        d2
+    }
+
     pub fn split(&self, axis: Axis3) -> (Self, Self) {
         let split = (self.max + self.min)[axis] * 0.5;
 
@@ -4,13 +4,13 @@ use std::convert::TryInto;
 
 pub type Triangle = [u32; 3];
 
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone)]
 #[repr(C)]
 pub struct Node {
     pub min: Vector3<f32>,
     pub left_or_offset: u32, // right = left + 1.
     pub max: Vector3<f32>,
-    pub count: u32, // leaf when std::u32::MAX, branch otherwise.
+    pub count: u32, // branch when u32::MAX, leaf (triangle count) otherwise.
 }
 
 impl Node {
@@ -22,31 +22,161 @@ impl Node {
             count: 0,
         }
     }
+
+    #[inline]
+    pub fn is_branch(&self) -> bool {
+        self.count == u32::MAX
+    }
+
+    #[inline]
+    pub fn aabb(&self) -> AABB3 {
+        AABB3 {
+            min: self.min,
+            max: self.max,
+        }
+    }
 }
 
+/// A split by the mean centroid whose smaller side has fewer than
+/// 1/`MIN_SPLIT_RATIO` of the triangles falls back to the median.
+const MIN_SPLIT_RATIO: usize = 8;
+
 #[derive(Debug)]
 pub struct Tree {
     pub nodes: Vec<Node>,
     pub triangles: Vec<Triangle>,
 }
 
+/// Borrowed nodes and triangles of a tree that may live outside a `Tree`,
+/// for example in a memory mapped tree file. All queries are implemented on
+/// the view; the ones on `Tree` forward to it.
+#[derive(Debug, Copy, Clone)]
+pub struct TreeView<'a> {
+    pub nodes: &'a [Node],
+    pub triangles: &'a [Triangle],
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum DegenerateTriangles {
+    /// Keep triangles with zero area or non-finite vertices. Non-finite
+    /// vertices do not contribute to the bounds.
+    Keep,
+    /// Leave triangles with zero area or non-finite vertices out of the tree.
+    Drop,
+}
+
+#[derive(Debug, Clone)]
+pub struct BuildOptions {
+    pub degenerate_triangles: DegenerateTriangles,
+}
+
+impl Default for BuildOptions {
+    fn default() -> Self {
+        BuildOptions {
+            degenerate_triangles: DegenerateTriangles::Keep,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum BuildError {
+    /// Index into the triangles passed to `Tree::try_new`.
+    VertexOutOfRange { triangle_index: u32 },
+    /// Triangles are indexed with `u32`.
+    TooManyTriangles,
+}
+
+impl std::fmt::Display for BuildError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            BuildError::VertexOutOfRange { triangle_index } => {
+                write!(f, "triangle {} refers to a missing vertex", triangle_index)
+            }
+            BuildError::TooManyTriangles => write!(f, "triangles do not fit in 32 bit indices"),
+        }
+    }
+}
+
+impl std::error::Error for BuildError {}
+
+#[inline]
+fn is_finite(p: Vector3<f32>) -> bool {
+    p.x.is_finite() && p.y.is_finite() && p.z.is_finite()
+}
+
+fn is_degenerate([p0, p1, p2]: [Vector3<f32>; 3]) -> bool {
+    let area2 = (p1 - p0).cross(p2 - p0).magnitude2();
+    !(area2 > 0.0 && area2.is_finite())
+}
+
+/// Bounds of the finite points, empty when there are none.
+fn finite_aabb<I>(points: I) -> AABB3
+where
+    I: IntoIterator<Item = Vector3<f32>>,
+{
+    let mut aabb = AABB3::default();
+    for point in points.into_iter().filter(|&point| is_finite(point)) {
+        aabb.include_point(point);
+    }
+    aabb
+}
+
 impl Tree {
+    /// Panics when a triangle refers to a missing vertex, see `try_new`.
     pub fn new(vertices: &[Vector3<f32>], triangles: &[Triangle]) -> Self {
+        match Self::try_new(vertices, triangles, &BuildOptions::default()) {
+            Ok(tree) => tree,
+            Err(error) => panic!("failed to build tree: {}", error),
+        }
+    }
+
+    /// Builds a tree over the triangles. Without triangles the tree consists
+    /// of an empty root leaf with empty bounds.
+    pub fn try_new(
+        vertices: &[Vector3<f32>],
+        triangles: &[Triangle],
+        options: &BuildOptions,
+    ) -> Result<Self, BuildError> {
+        let triangle_count: u32 = triangles
+            .len()
+            .try_into()
+            .map_err(|_| BuildError::TooManyTriangles)?;
+        if let Some(triangle_index) = triangles
+            .iter()
+            .position(|t| t.iter().any(|&i| i as usize >= vertices.len()))
+        {
+            return Err(BuildError::VertexOutOfRange {
+                triangle_index: triangle_index as u32,
+            });
+        }
+
+        let triangle_vertices = |t: &Triangle| {
+            [
+                vertices[t[0] as usize],
+                vertices[t[1] as usize],
+                vertices[t[2] as usize],
+            ]
+        };
+
         let centroids: Vec<Vector3<f32>> = triangles
             .iter()
-            .map(|&t| {
-                (vertices[t[0] as usize] + vertices[t[1] as usize] + vertices[t[2] as usize]) / 3.0
+            .map(|t| {
+                let [p0, p1, p2] = triangle_vertices(t);
+                (p0 + p1 + p2) / 3.0
             })
             .collect();
 
-        let centroid_aabb = AABB3::from_points(centroids.iter().copied()).unwrap();
-        let triangle_indices: Vec<u32> = (0u32..triangles.len().try_into().unwrap())
-            .into_iter()
+        let triangle_indices: Vec<u32> = (0..triangle_count)
+            .filter(|&i| match options.degenerate_triangles {
+                DegenerateTriangles::Keep => true,
+                DegenerateTriangles::Drop => !is_degenerate(triangle_vertices(&triangles[i as usize])),
+            })
             .collect();
+        let centroid_aabb = finite_aabb(triangle_indices.iter().map(|&i| centroids[i as usize]));
 
         let mut tree = Tree {
             nodes: Vec::new(),
-            triangles: Vec::new(),
+            triangles: Vec::with_capacity(triangle_indices.len()),
         };
 
         tree.nodes.push(Node::unprocessed());
@@ -54,8 +184,8 @@ impl Tree {
 
         let triangle_aabb = process(
             &mut tree,
-            &vertices,
-            &triangles,
+            vertices,
+            triangles,
             &centroids,
             0,
             0,
@@ -87,40 +217,54 @@ impl Tree {
                         .iter()
                         .map(|&index| triangles[index as usize]),
                 );
-                AABB3::from_points(
-                    tree.triangles
+                finite_aabb(
+                    tree.triangles[offset as usize..]
                         .iter()
-                        .skip(offset as usize)
-                        .take(triangle_count as usize)
                         .flat_map(|triangle| triangle.iter().map(|&i| vertices[i as usize])),
                 )
-                .unwrap()
             } else {
                 let split_axis = (centroid_aabb.max - centroid_aabb.min).largest_component();
                 // let split_value =
                 //     (centroid_aabb.min[split_axis] + centroid_aabb.max[split_axis]) * 0.5;
-                let scale = 1.0 / triangle_indices.len() as f32;
-                let split_value = triangle_indices
+                let finite_count = triangle_indices
+                    .iter()
+                    .filter(|&&i| is_finite(centroids[i as usize]))
+                    .count();
+                let scale = 1.0 / finite_count as f32;
+                let split_value: f32 = triangle_indices
                     .iter()
-                    .map(|&i| centroids[i as usize][split_axis] * scale)
+                    .map(|&i| centroids[i as usize])
+                    .filter(|&centroid| is_finite(centroid))
+                    .map(|centroid| centroid[split_axis] * scale)
                     .sum();
 
                 let mut left_indices = Vec::with_capacity(triangle_count as usize);
                 let mut right_indices = Vec::with_capacity(triangle_count as usize);
-                let mut left_centroid_aabb = AABB3::default();
-                let mut right_centroid_aabb = AABB3::default();
 
                 for &triangle_index in triangle_indices.iter() {
-                    let centroid = centroids[triangle_index as usize];
-                    let (centroid_aabb, indices) = if centroid[split_axis] < split_value {
-                        (&mut left_centroid_aabb, &mut left_indices)
+                    if centroids[triangle_index as usize][split_axis] < split_value {
+                        left_indices.push(triangle_index);
                     } else {
-                        (&mut right_centroid_aabb, &mut right_indices)
-                    };
-                    centroid_aabb.include_point(centroid);
-                    indices.push(triangle_index);
+                        right_indices.push(triangle_index);
+                    }
                 }
 
+                let smaller_count = left_indices.len().min(right_indices.len());
+                if smaller_count * MIN_SPLIT_RATIO < triangle_indices.len() {
+                    // Centroids that coincide, are not finite, or cluster
+                    // around a few outliers pull the mean to one side. Split
+                    // by count at the median so the depth stays logarithmic.
+                    let mut sorted = triangle_indices.to_vec();
+                    sorted.sort_by(|&a, &b| {
+                        centroids[a as usize][split_axis].total_cmp(&centroids[b as usize][split_axis])
+                    });
+                    right_indices = sorted.split_off(sorted.len() / 2);
+                    left_indices = sorted;
+                }
+
+                let left_centroid_aabb = finite_aabb(left_indices.iter().map(|&i| centroids[i as usize]));
+                let right_centroid_aabb = finite_aabb(right_indices.iter().map(|&i| centroids[i as usize]));
+
                 // Branch.
                 let left_node_index: u32 = tree.nodes.len().try_into().unwrap();
                 tree.nodes.push(Node::unprocessed());
@@ -157,8 +301,140 @@ impl Tree {
             tree.nodes[node_index as usize].max = triangle_aabb.max;
 
             triangle_aabb
+        }
+
+        Ok(tree)
+    }
+
+    #[inline]
+    pub fn view(&self) -> TreeView<'_> {
+        TreeView {
+            nodes: &self.nodes,
+            triangles: &self.triangles,
+        }
+    }
+
+    #[inline]
+    pub fn leaf_triangles(&self, node: &Node) -> &[Triangle] {
+        self.view().leaf_triangles(node)
+    }
+
+    #[inline]
+    pub fn triangle_vertices(&self, vertices: &[Vector3<f32>], triangle_index: u32) -> [Vector3<f32>; 3] {
+        self.view().triangle_vertices(vertices, triangle_index)
+    }
+}
+
+impl<'a> TreeView<'a> {
+    /// Copies the nodes and triangles into an owned tree.
+    pub fn to_tree(&self) -> Tree {
+        Tree {
+            nodes: self.nodes.to_vec(),
+            triangles: self.triangles.to_vec(),
+        }
+    }
+
+    #[inline]
+    pub fn leaf_triangles(&self, node: &Node) -> &'a [Triangle] {
+        let offset = node.left_or_offset as usize;
+        &self.triangles[offset..offset + node.count as usize]
+    }
+
+    #[inline]
+    pub fn triangle_vertices(&self, vertices: &[Vector3<f32>], triangle_index: u32) -> [Vector3<f32>; 3] {
+        let t = self.triangles[triangle_index as usize];
+        [
+            vertices[t[0] as usize],
+            vertices[t[1] as usize],
+            vertices[t[2] as usize],
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn subtree_triangle_count(tree: &Tree, node_index: u32) -> u32 {
+        let node = &tree.nodes[node_index as usize];
+        if node.is_branch() {
+            subtree_triangle_count(tree, node.left_or_offset) + subtree_triangle_count(tree, node.left_or_offset + 1)
+        } else {
+            node.count
+        }
+    }
+
+    #[test]
+    fn coincident_centroids_with_outlier() {
+        let vertices = vec![
+            Vector3 { x: 0.0, y: 0.0, z: 0.0 },
+            Vector3 { x: 1.0, y: 0.0, z: 0.0 },
+            Vector3 { x: 0.0, y: 1.0, z: 0.0 },
+            Vector3 { x: 1000.0, y: 0.0, z: 0.0 },
+            Vector3 { x: 1001.0, y: 0.0, z: 0.0 },
+            Vector3 { x: 1000.0, y: 1.0, z: 0.0 },
+        ];
+        let mut triangles = vec![[0, 1, 2]; 1023];
+        triangles.push([3, 4, 5]);
+
+        let tree = Tree::new(&vertices, &triangles);
+        let stats = tree.validate(&vertices).unwrap();
+        assert_eq!(1024, stats.triangle_references);
+        assert!(stats.leaf_size_histogram.keys().all(|&size| size <= 16));
+        // 1024 triangles in leaves of 16 take 6 levels of median splits.
+        assert_eq!(6, stats.max_depth);
+
+        // The mean split would put the outlier alone on one side.
+        let root = &tree.nodes[0];
+        assert_eq!(512, subtree_triangle_count(&tree, root.left_or_offset));
+        assert_eq!(512, subtree_triangle_count(&tree, root.left_or_offset + 1));
+    }
+
+    #[test]
+    fn empty_and_degenerate_input() {
+        let tree = Tree::try_new(&[], &[], &BuildOptions::default()).unwrap();
+        assert!(tree.triangles.is_empty());
+        assert!(!tree.nodes[0].is_branch());
+        assert_eq!(0, tree.nodes[0].count);
+        assert!(tree.validate(&[]).is_ok());
+
+        // Distinct triangles that all share one centroid.
+        let mut vertices = Vec::new();
+        let mut triangles = Vec::new();
+        for i in 0..1000u32 {
+            let angle = i as f32 * 0.01;
+            for k in 0..3 {
+                let a = angle + k as f32 * std::f32::consts::TAU / 3.0;
+                vertices.push(Vector3 { x: a.cos(), y: a.sin(), z: 0.0 });
+            }
+            triangles.push([i * 3, i * 3 + 1, i * 3 + 2]);
+        }
+        let tree = Tree::new(&vertices, &triangles);
+        let stats = tree.validate(&vertices).unwrap();
+        assert_eq!(1000, stats.triangle_references);
+        assert!(stats.leaf_size_histogram.keys().all(|&size| size <= 16));
+        assert!(stats.max_depth < 18);
+
+        // A collapsed and a non-finite triangle.
+        vertices.push(Vector3 { x: 0.0, y: 0.0, z: 0.0 });
+        vertices.push(Vector3 { x: f32::NAN, y: 0.0, z: 0.0 });
+        let point = vertices.len() as u32 - 2;
+        triangles.push([point, point, point]);
+        triangles.push([0, 1, point + 1]);
+
+        let kept = Tree::new(&vertices, &triangles);
+        assert_eq!(1002, kept.validate(&vertices).unwrap().triangle_references);
+        assert!(kept.nodes[0].max.x.is_finite());
+
+        let options = BuildOptions {
+            degenerate_triangles: DegenerateTriangles::Drop,
         };
+        let dropped = Tree::try_new(&vertices, &triangles, &options).unwrap();
+        assert_eq!(1000, dropped.validate(&vertices).unwrap().triangle_references);
 
-        tree
+        assert_eq!(
+            Err(BuildError::VertexOutOfRange { triangle_index: 1 }),
+            Tree::try_new(&vertices, &[[0, 1, 2], [0, 1, 9999]], &options).map(|_| ())
+        );
     }
 }
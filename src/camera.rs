@@ -1,5 +1,15 @@
 use cgmath::*;
+use crate::aabb::*;
+use crate::bvh::*;
 use crate::clamp::*;
+use crate::ray::*;
+
+/// Returns the angle equivalent to `angle` within half a turn of `reference`.
+#[inline]
+pub fn nearest_equivalent_angle(angle: Rad<f32>, reference: Rad<f32>) -> Rad<f32> {
+    reference + Rad((angle - reference + Rad::turn_div_2()).0.rem_euclid(Rad::full_turn().0))
+        - Rad::turn_div_2()
+}
 
 #[derive(Debug, Copy, Clone)]
 #[repr(C)]
@@ -43,6 +53,57 @@ impl CameraTransform {
         };
     }
 
+    /// Like `update` but walks over the colliders instead of flying: the
+    /// movement stays horizontal, a sphere around the eye is swept through
+    /// the colliders in small steps and pushed out of anything it touches,
+    /// and a downward ray keeps the eye at a fixed height above the floor.
+    pub fn update_walking(&mut self, delta: &CameraDelta, walk: &WalkProperties, colliders: &[Collider]) {
+        let displacement = Quaternion::from_axis_angle(Vector3::unit_y(), self.yaw)
+            * Vector3::new(delta.position.x, 0.0, delta.position.z)
+            * delta.time;
+
+        self.update(&CameraDelta {
+            position: Vector3::zero(),
+            ..*delta
+        });
+
+        // Never move more than half the radius per step so we can't tunnel
+        // through thin walls.
+        let step_count = (displacement.magnitude() / (walk.radius * 0.5)).ceil().max(1.0);
+        let step = displacement / step_count;
+        for _ in 0..step_count as u32 {
+            self.position += step;
+            for _ in 0..4 {
+                if !walk.push_out(&mut self.position, colliders) {
+                    break;
+                }
+            }
+        }
+
+        let down = Ray {
+            origin: self.position,
+            direction: -Vector3::unit_y(),
+        };
+        let floor_t = colliders
+            .iter()
+            .filter_map(|collider| {
+                // Affine transforms preserve the ray parameter, so `t` is
+                // also the distance in world space.
+                let down_in_obj = Ray {
+                    origin: collider.wld_to_obj.transform_point(down.origin),
+                    direction: collider.wld_to_obj.transform_vector(down.direction),
+                };
+                collider
+                    .tree
+                    .ray_hit(collider.vertices, down_in_obj, f32::INFINITY)
+                    .map(|hit| hit.intersection.t)
+            })
+            .fold(f32::INFINITY, f32::min);
+        if floor_t.is_finite() {
+            self.position.y += walk.eye_height - floor_t;
+        }
+    }
+
     #[inline]
     pub fn correction(&self) -> CameraCorrection {
         CameraCorrection {
@@ -121,6 +182,21 @@ impl Camera {
         })
     }
 
+    #[inline]
+    pub fn update_walking(&mut self, delta: &CameraDelta, walk: &WalkProperties, colliders: &[Collider]) {
+        self.transform.update_walking(
+            &CameraDelta {
+                time: delta.time,
+                position: delta.position * self.properties.positional_velocity,
+                yaw: delta.yaw * self.properties.angular_velocity,
+                pitch: delta.pitch * self.properties.angular_velocity,
+                fovy: delta.fovy * self.properties.zoom_velocity,
+            },
+            walk,
+            colliders,
+        )
+    }
+
     #[inline]
     pub fn interpolate(a: Self, b: Self, t: f32) -> Camera {
         Camera {
@@ -130,14 +206,172 @@ impl Camera {
     }
 }
 
+/// A mesh instance the camera collides with when walking.
+#[derive(Debug, Copy, Clone)]
+pub struct Collider<'a> {
+    pub tree: TreeView<'a>,
+    pub vertices: &'a [crate::vector::Vector3<f32>],
+    pub obj_to_wld: Matrix4<f32>,
+    pub wld_to_obj: Matrix4<f32>,
+}
+
+impl<'a> Collider<'a> {
+    /// Returns `None` when `obj_to_wld` is not invertible.
+    pub fn new(
+        tree: TreeView<'a>,
+        vertices: &'a [crate::vector::Vector3<f32>],
+        obj_to_wld: Matrix4<f32>,
+    ) -> Option<Self> {
+        obj_to_wld.invert().map(|wld_to_obj| Collider {
+            tree,
+            vertices,
+            obj_to_wld,
+            wld_to_obj,
+        })
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct WalkProperties {
+    /// Radius of the collision sphere around the eye.
+    pub radius: f32,
+    /// Height of the eye above the floor.
+    pub eye_height: f32,
+}
+
+impl WalkProperties {
+    /// Moves the sphere at `position` out of the closest penetrating
+    /// triangle of every collider. The closest point is found in object
+    /// space, so it is only exact for rigid and uniformly scaled instances.
+    /// Returns whether it moved.
+    fn push_out(&self, position: &mut Point3<f32>, colliders: &[Collider]) -> bool {
+        let mut moved = false;
+        for collider in colliders {
+            let point_in_obj = collider.wld_to_obj.transform_point(*position);
+            let point = crate::vector::Vector3::from(Into::<[f32; 3]>::into(point_in_obj));
+            let closest = match collider.tree.closest_point(collider.vertices, point) {
+                Some(closest) => closest,
+                None => continue,
+            };
+            let closest_in_wld = collider
+                .obj_to_wld
+                .transform_point(Point3::from(Into::<[f32; 3]>::into(closest.point)));
+            let away = *position - closest_in_wld;
+            let distance = away.magnitude();
+            if distance >= self.radius {
+                continue;
+            }
+            if distance > 0.0 {
+                *position += away * ((self.radius - distance) / distance);
+                moved = true;
+            }
+        }
+        moved
+    }
+}
+
+/// A camera that rotates around and looks at a target point.
+#[derive(Debug, Copy, Clone)]
+pub struct OrbitCamera {
+    pub properties: CameraProperties,
+    pub target: Point3<f32>,
+    pub distance: f32,
+    pub yaw: Rad<f32>,
+    pub pitch: Rad<f32>,
+    pub fovy: Rad<f32>,
+}
+
+impl OrbitCamera {
+    /// Starts orbiting around the point `distance` in front of the camera
+    /// without changing the view.
+    #[inline]
+    pub fn from_camera(camera: &Camera, distance: f32) -> Self {
+        let forward = camera.transform.rot_to_parent() * -Vector3::unit_z();
+        Self {
+            properties: camera.properties,
+            target: camera.transform.position + forward * distance,
+            distance,
+            yaw: camera.transform.yaw,
+            pitch: camera.transform.pitch,
+            fovy: camera.transform.fovy,
+        }
+    }
+
+    /// Keeps the target between the near and the far plane.
+    #[inline]
+    fn distance_range(&self) -> (f32, f32) {
+        (-self.properties.z1, -self.properties.z0)
+    }
+
+    /// Rotates with `yaw` and `pitch`, pans the target with the x and y
+    /// components of `position` and dollies with its z component. Panning
+    /// and dollying scale with the distance to the target, which stays
+    /// between the near and the far plane.
+    #[inline]
+    pub fn update(&mut self, delta: &CameraDelta) {
+        let time = delta.time;
+        let position = delta.position * self.properties.positional_velocity * time;
+        let rot_to_parent = self.transform().rot_to_parent();
+
+        self.target += rot_to_parent * Vector3::new(position.x, position.y, 0.0) * self.distance;
+        self.distance = (self.distance * position.z.exp()).clamp_range(self.distance_range());
+        self.yaw += delta.yaw * self.properties.angular_velocity * time;
+        self.pitch = (self.pitch + delta.pitch * self.properties.angular_velocity * time)
+            .clamp_range(CameraTransform::pitch_range());
+        self.fovy = (self.fovy + delta.fovy * self.properties.zoom_velocity * time)
+            .clamp_range(CameraTransform::fovy_range());
+    }
+
+    /// Moves the target to the center of the box and sets the distance so
+    /// the box fits in view for the current vertical field of view and the
+    /// given aspect ratio (width over height). The distance is clamped like
+    /// in `update`. Leaves the camera unchanged when the box is empty or not
+    /// finite.
+    #[inline]
+    pub fn frame_aabb(&mut self, aabb: &AABB3, aspect: f32) {
+        let (min, max) = (aabb.min, aabb.max);
+        let valid = |a: f32, b: f32| a.is_finite() && b.is_finite() && a <= b;
+        if !(valid(min.x, max.x) && valid(min.y, max.y) && valid(min.z, max.z)) {
+            return;
+        }
+        let center = (aabb.min + aabb.max) / 2.0;
+        let radius = (aabb.max - aabb.min).magnitude() / 2.0;
+        let half_fovy = self.fovy.0 / 2.0;
+        let half_fovx = (half_fovy.tan() * aspect).atan();
+        self.target = Point3::new(center.x, center.y, center.z);
+        self.distance = (radius / half_fovy.min(half_fovx).sin()).clamp_range(self.distance_range());
+    }
+
+    #[inline]
+    pub fn transform(&self) -> CameraTransform {
+        let mut transform = CameraTransform {
+            position: self.target,
+            yaw: self.yaw,
+            pitch: self.pitch,
+            fovy: self.fovy,
+        };
+        transform.position += transform.rot_to_parent() * Vector3::unit_z() * self.distance;
+        transform
+    }
+
+    #[inline]
+    pub fn to_camera(&self) -> Camera {
+        Camera {
+            properties: self.properties,
+            transform: self.transform(),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct SmoothCamera {
     pub properties: CameraProperties,
     pub current_transform: CameraTransform,
     pub target_transform: CameraTransform,
     pub smooth_enabled: bool,
-    pub current_smoothness: f32,
-    pub maximum_smoothness: f32,
+    /// Seconds it takes the current transform to cover half of the
+    /// remaining distance to the target transform.
+    pub half_life: f32,
 }
 
 impl SmoothCamera {
@@ -155,19 +389,24 @@ impl SmoothCamera {
         self.target_transform.correct(&correction);
         self.current_transform.correct(&correction);
 
-        self.current_smoothness = self.target_smoothness() * 0.2 + self.current_smoothness * 0.8;
+        // Exponential decay towards the target. Because the remaining
+        // fraction multiplies over updates, the result only depends on the
+        // total time and not on how it is divided into frames.
+        let half_life = self.active_half_life();
+        let t = if half_life > 0.0 {
+            1.0 - 0.5f32.powf(delta.time / half_life)
+        } else {
+            1.0
+        };
 
-        self.current_transform = CameraTransform::interpolate(
-            self.current_transform,
-            self.target_transform,
-            1.0 - self.current_smoothness,
-        );
+        self.current_transform =
+            CameraTransform::interpolate(self.current_transform, self.target_transform, t);
     }
 
     #[inline]
-    pub fn target_smoothness(&self) -> f32 {
+    pub fn active_half_life(&self) -> f32 {
         if self.smooth_enabled {
-            self.maximum_smoothness
+            self.half_life
         } else {
             0.0
         }
@@ -215,13 +454,10 @@ impl TransitionCamera {
 
         // Bring current yaw within (-half turn, half turn) of
         // the target yaw without changing the actual angle.
-        let start_yaw = self.start_camera.transform.yaw;
-        let end_yaw = update.end_camera.transform.yaw;
-        self.start_camera.transform.yaw = end_yaw
-            + Rad((start_yaw - end_yaw + Rad::turn_div_2())
-                .0
-                .rem_euclid(Rad::full_turn().0))
-            - Rad::turn_div_2();
+        self.start_camera.transform.yaw = nearest_equivalent_angle(
+            self.start_camera.transform.yaw,
+            update.end_camera.transform.yaw,
+        );
 
         let x = self.progress;
         let t = x * x * (3.0 - 2.0 * x);
@@ -229,3 +465,198 @@ impl TransitionCamera {
         self.current_camera = Camera::interpolate(self.start_camera, *update.end_camera, t);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn camera() -> Camera {
+        Camera {
+            properties: CameraProperties {
+                z0: -200.0,
+                z1: -0.5,
+                positional_velocity: 1.0,
+                angular_velocity: 1.0,
+                zoom_velocity: 1.0,
+            },
+            transform: CameraTransform {
+                position: Point3::new(1.0, 2.0, 3.0),
+                yaw: Deg(30.0).into(),
+                pitch: Deg(-20.0).into(),
+                fovy: Deg(60.0).into(),
+            },
+        }
+    }
+
+    #[test]
+    fn orbit_camera_keeps_view_and_frames_aabb() {
+        let camera = camera();
+        let mut orbit = OrbitCamera::from_camera(&camera, 4.0);
+        let transform = orbit.transform();
+        assert!((transform.position - camera.transform.position).magnitude() < 1e-5);
+
+        let aabb = AABB3 {
+            min: [-1.0, -1.0, -1.0].into(),
+            max: [3.0, 1.0, 1.0].into(),
+        };
+        orbit.frame_aabb(&aabb, 1.5);
+        assert_eq!(Point3::new(1.0, 0.0, 0.0), orbit.target);
+        let radius = 6f32.sqrt();
+        let half_fovy = orbit.fovy.0 / 2.0;
+        assert!((orbit.distance * half_fovy.sin() - radius).abs() < 1e-5);
+
+        // Empty and infinite boxes leave the camera alone.
+        let framed = (orbit.target, orbit.distance);
+        orbit.frame_aabb(&AABB3::default(), 1.5);
+        orbit.frame_aabb(&AABB3 { min: aabb.min, max: [f32::INFINITY, 1.0, 1.0].into() }, 1.5);
+        assert_eq!(framed, (orbit.target, orbit.distance));
+
+        // Boxes too large or too small for the depth range clamp the distance.
+        let (near, far) = orbit.distance_range();
+        orbit.frame_aabb(&AABB3 { min: [-1e3, -1e3, -1e3].into(), max: [1e3, 1e3, 1e3].into() }, 1.5);
+        assert_eq!(far, orbit.distance);
+        orbit.frame_aabb(&AABB3 { min: [0.0, 0.0, 0.0].into(), max: [0.0, 0.0, 0.0].into() }, 1.5);
+        assert_eq!(near, orbit.distance);
+    }
+
+    #[test]
+    fn orbit_camera_rotates_around_target_and_clamps_distance() {
+        let mut orbit = OrbitCamera::from_camera(&camera(), 4.0);
+        let target = orbit.target;
+        let orbit_delta = |dolly: f32| CameraDelta {
+            time: 0.5,
+            position: Vector3::new(0.0, 0.0, dolly),
+            yaw: Rad(1.0),
+            pitch: Rad(0.5),
+            fovy: Rad(0.0),
+        };
+
+        orbit.update(&orbit_delta(0.0));
+        assert_eq!(target, orbit.target);
+        assert_eq!(4.0, orbit.distance);
+        assert!((Rad::from(Deg(30.0)) + Rad(0.5) - orbit.yaw).0.abs() < 1e-6);
+        assert!(((orbit.transform().position - target).magnitude() - 4.0).abs() < 1e-5);
+
+        // Dollying far out stops at the far plane, far in at the near plane.
+        orbit.update(&orbit_delta(100.0));
+        assert_eq!(target, orbit.target);
+        assert_eq!(200.0, orbit.distance);
+        orbit.update(&orbit_delta(-100.0));
+        assert_eq!(target, orbit.target);
+        assert_eq!(0.5, orbit.distance);
+        assert!(((orbit.transform().position - target).magnitude() - 0.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn smooth_camera_is_frame_rate_independent() {
+        let run = |rate: u32| {
+            let camera = camera();
+            let mut smooth = SmoothCamera {
+                properties: camera.properties,
+                current_transform: camera.transform,
+                target_transform: camera.transform,
+                smooth_enabled: true,
+                half_life: 0.1,
+            };
+            let time = 1.0 / rate as f32;
+            // Move for half a second, turn for half a second, then settle for a second.
+            for frame in 0..rate * 2 {
+                let moving = if frame < rate / 2 { 1.0 } else { 0.0 };
+                let turning = if frame >= rate / 2 && frame < rate { 1.0 } else { 0.0 };
+                smooth.update(&CameraDelta {
+                    time,
+                    position: Vector3::new(2.0, 0.0, -4.0) * moving,
+                    yaw: Rad(1.0 * turning),
+                    pitch: Rad(0.5 * turning),
+                    fovy: Rad(0.0),
+                });
+            }
+            smooth.current_transform
+        };
+
+        let reference = run(144);
+        for &rate in [30, 60].iter() {
+            let transform = run(rate);
+            assert!((transform.position - reference.position).magnitude() < 1e-3);
+            assert!((transform.yaw - reference.yaw).0.abs() < 1e-3);
+            assert!((transform.pitch - reference.pitch).0.abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn walking_follows_floor_and_stops_at_wall() {
+        let v = |x: f32, y: f32, z: f32| crate::vector::Vector3 { x, y, z };
+        let vertices = vec![
+            // Floor at y = 0.
+            v(-10.0, 0.0, -10.0),
+            v(-10.0, 0.0, 10.0),
+            v(10.0, 0.0, 10.0),
+            v(10.0, 0.0, -10.0),
+            // Wall at z = -5.
+            v(-10.0, 0.0, -5.0),
+            v(10.0, 0.0, -5.0),
+            v(10.0, 10.0, -5.0),
+            v(-10.0, 10.0, -5.0),
+        ];
+        let triangles = vec![[0, 1, 2], [0, 2, 3], [4, 5, 6], [4, 6, 7]];
+        let tree = Tree::new(&vertices, &triangles);
+        let colliders = [Collider::new(tree.view(), &vertices, Matrix4::identity()).unwrap()];
+        let walk = WalkProperties {
+            radius: 0.5,
+            eye_height: 1.7,
+        };
+
+        let mut transform = CameraTransform {
+            position: Point3::new(0.0, 3.0, 0.0),
+            yaw: Rad(0.0),
+            pitch: Deg(-30.0).into(),
+            fovy: Deg(90.0).into(),
+        };
+        // Walk forward, looking down, for much longer than it takes to reach the wall.
+        for _ in 0..100 {
+            transform.update_walking(
+                &CameraDelta {
+                    time: 1.0 / 60.0,
+                    position: Vector3::new(0.0, 0.0, -10.0),
+                    yaw: Rad(0.0),
+                    pitch: Rad(0.0),
+                    fovy: Rad(0.0),
+                },
+                &walk,
+                &colliders,
+            );
+        }
+
+        assert!((transform.position.y - walk.eye_height).abs() < 1e-4);
+        assert!(transform.position.z >= -5.0 + walk.radius - 1e-4);
+        assert!(transform.position.z < -5.0 + walk.radius + 0.1);
+
+        // The same mesh raised by 2 and turned half a turn puts the floor at
+        // y = 2 and the wall at z = 5.
+        let obj_to_wld =
+            Matrix4::from_translation(Vector3::new(0.0, 2.0, 0.0)) * Matrix4::from_angle_y(Deg(180.0));
+        let colliders = [Collider::new(tree.view(), &vertices, obj_to_wld).unwrap()];
+        let mut transform = CameraTransform {
+            position: Point3::new(0.0, 5.0, 0.0),
+            yaw: Deg(180.0).into(),
+            ..transform
+        };
+        for _ in 0..100 {
+            transform.update_walking(
+                &CameraDelta {
+                    time: 1.0 / 60.0,
+                    position: Vector3::new(0.0, 0.0, -10.0),
+                    yaw: Rad(0.0),
+                    pitch: Rad(0.0),
+                    fovy: Rad(0.0),
+                },
+                &walk,
+                &colliders,
+            );
+        }
+
+        assert!((transform.position.y - 2.0 - walk.eye_height).abs() < 1e-3);
+        assert!(transform.position.z <= 5.0 - walk.radius + 1e-3);
+        assert!(transform.position.z > 5.0 - walk.radius - 0.1);
+    }
+}
@@ -0,0 +1,187 @@
+use cgmath::*;
+use std::io::{BufRead, Write};
+
+use crate::camera::*;
+
+pub const BOOKMARK_COUNT: usize = 9;
+
+/// Numbered slots holding saved cameras.
+#[derive(Debug, Default, Clone)]
+pub struct CameraBookmarks {
+    pub slots: [Option<Camera>; BOOKMARK_COUNT],
+}
+
+#[derive(Debug)]
+pub enum CameraBookmarksError {
+    Io(std::io::Error),
+    /// A line could not be parsed, lines are numbered from 1.
+    Parse { line: usize },
+    /// The slot number on a line is out of range.
+    Slot { line: usize },
+}
+
+impl std::fmt::Display for CameraBookmarksError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CameraBookmarksError::Io(error) => write!(f, "{}", error),
+            CameraBookmarksError::Parse { line } => {
+                write!(f, "line {}: expected a slot followed by 11 numbers", line)
+            }
+            CameraBookmarksError::Slot { line } => write!(
+                f,
+                "line {}: slot must be between 1 and {}",
+                line, BOOKMARK_COUNT
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CameraBookmarksError {}
+
+impl From<std::io::Error> for CameraBookmarksError {
+    fn from(error: std::io::Error) -> Self {
+        CameraBookmarksError::Io(error)
+    }
+}
+
+impl CameraBookmarks {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Slots are numbered from 1 to match the keys used to access them.
+    #[inline]
+    pub fn get(&self, slot: usize) -> Option<&Camera> {
+        self.slots.get(slot.checked_sub(1)?)?.as_ref()
+    }
+
+    /// Stores the camera in the slot, panics when the slot is out of range.
+    #[inline]
+    pub fn set(&mut self, slot: usize, camera: Camera) {
+        self.slots[slot - 1] = Some(camera);
+    }
+
+    /// Writes one bookmark per line: the slot, the camera properties z0, z1,
+    /// positional, angular and zoom velocity, followed by the position x, y
+    /// and z, yaw, pitch and vertical field of view in radians.
+    pub fn write<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        writeln!(
+            writer,
+            "# slot z0 z1 positional_velocity angular_velocity zoom_velocity x y z yaw pitch fovy"
+        )?;
+        for (index, camera) in self.slots.iter().enumerate() {
+            if let Some(Camera { properties: p, transform: t }) = camera {
+                writeln!(
+                    writer,
+                    "{} {} {} {} {} {} {} {} {} {} {} {}",
+                    index + 1,
+                    p.z0,
+                    p.z1,
+                    p.positional_velocity,
+                    p.angular_velocity,
+                    p.zoom_velocity,
+                    t.position.x,
+                    t.position.y,
+                    t.position.z,
+                    t.yaw.0,
+                    t.pitch.0,
+                    t.fovy.0
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads the format produced by `write`. Empty lines and lines starting
+    /// with `#` are ignored, later lines overwrite earlier ones with the same
+    /// slot.
+    pub fn read<R: BufRead>(reader: R) -> Result<Self, CameraBookmarksError> {
+        let mut bookmarks = CameraBookmarks::new();
+        for (index, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut words = line.split_whitespace();
+            let slot = words
+                .next()
+                .and_then(|word| word.parse::<usize>().ok())
+                .ok_or(CameraBookmarksError::Parse { line: index + 1 })?;
+            if slot == 0 || slot > BOOKMARK_COUNT {
+                return Err(CameraBookmarksError::Slot { line: index + 1 });
+            }
+
+            let v = words
+                .map(|word| word.parse::<f32>())
+                .collect::<Result<Vec<f32>, _>>()
+                .ok()
+                .filter(|values| values.len() == 11)
+                .ok_or(CameraBookmarksError::Parse { line: index + 1 })?;
+
+            bookmarks.set(
+                slot,
+                Camera {
+                    properties: CameraProperties {
+                        z0: v[0],
+                        z1: v[1],
+                        positional_velocity: v[2],
+                        angular_velocity: v[3],
+                        zoom_velocity: v[4],
+                    },
+                    transform: CameraTransform {
+                        position: Point3::new(v[5], v[6], v[7]),
+                        yaw: Rad(v[8]),
+                        pitch: Rad(v[9]),
+                        fovy: Rad(v[10]),
+                    },
+                },
+            );
+        }
+        Ok(bookmarks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bookmarks_round_trip() {
+        let camera = Camera {
+            properties: CameraProperties {
+                z0: -200.0,
+                z1: -0.5,
+                positional_velocity: 0.2,
+                angular_velocity: 0.4,
+                zoom_velocity: 0.5,
+            },
+            transform: CameraTransform {
+                position: Point3::new(1.5, -2.25, 3.0),
+                yaw: Rad(0.1),
+                pitch: Rad(-0.2),
+                fovy: Rad(1.3),
+            },
+        };
+
+        let mut bookmarks = CameraBookmarks::new();
+        bookmarks.set(3, camera);
+        bookmarks.set(9, camera);
+
+        let mut bytes = Vec::new();
+        bookmarks.write(&mut bytes).unwrap();
+        let read = CameraBookmarks::read(&bytes[..]).unwrap();
+
+        assert!(read.get(1).is_none());
+        assert!(read.get(0).is_none());
+        let read_camera = read.get(3).unwrap();
+        assert_eq!(camera.transform.position, read_camera.transform.position);
+        assert_eq!(camera.transform.fovy, read_camera.transform.fovy);
+        assert_eq!(camera.properties.z0, read_camera.properties.z0);
+        assert!(read.get(9).is_some());
+
+        assert!(CameraBookmarks::read(&b"10 0 0 0 0 0 0 0 0 0 0 0\n"[..]).is_err());
+    }
+}
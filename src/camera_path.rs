@@ -0,0 +1,230 @@
+use cgmath::*;
+use std::io::{BufRead, Write};
+
+use crate::camera::*;
+
+#[derive(Debug, Copy, Clone)]
+pub struct Keyframe {
+    pub time: f32,
+    pub transform: CameraTransform,
+}
+
+/// Camera transforms keyed at increasing times. Sampling interpolates the
+/// position, yaw, pitch and field of view with a cubic Hermite spline whose
+/// tangents are Catmull-Rom style finite differences, and always turns the
+/// shortest way around between consecutive yaws.
+#[derive(Debug, Default, Clone)]
+pub struct CameraPath {
+    pub keyframes: Vec<Keyframe>,
+}
+
+#[derive(Debug)]
+pub enum CameraPathError {
+    Io(std::io::Error),
+    /// A line could not be parsed, lines are numbered from 1.
+    Parse { line: usize },
+    /// Keyframe times must increase.
+    Order { line: usize },
+}
+
+impl std::fmt::Display for CameraPathError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CameraPathError::Io(error) => write!(f, "{}", error),
+            CameraPathError::Parse { line } => write!(f, "line {}: expected 7 numbers", line),
+            CameraPathError::Order { line } => {
+                write!(f, "line {}: keyframe time does not increase", line)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CameraPathError {}
+
+impl From<std::io::Error> for CameraPathError {
+    fn from(error: std::io::Error) -> Self {
+        CameraPathError::Io(error)
+    }
+}
+
+fn to_array(transform: &CameraTransform) -> [f32; 6] {
+    [
+        transform.position.x,
+        transform.position.y,
+        transform.position.z,
+        transform.yaw.0,
+        transform.pitch.0,
+        transform.fovy.0,
+    ]
+}
+
+fn from_array(a: [f32; 6]) -> CameraTransform {
+    CameraTransform {
+        position: Point3::new(a[0], a[1], a[2]),
+        yaw: Rad(a[3]),
+        pitch: Rad(a[4]),
+        fovy: Rad(a[5]),
+    }
+}
+
+impl CameraPath {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[inline]
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map_or(0.0, |keyframe| keyframe.time)
+    }
+
+    /// Appends a keyframe `delta_time` after the last one, or at time zero
+    /// for the first keyframe.
+    pub fn push(&mut self, transform: CameraTransform, delta_time: f32) {
+        let time = match self.keyframes.last() {
+            Some(last) => last.time + delta_time,
+            None => 0.0,
+        };
+        self.keyframes.push(Keyframe { time, transform });
+    }
+
+    pub fn sample(&self, time: f32) -> Option<CameraTransform> {
+        let keyframes = &self.keyframes[..];
+        let last = keyframes.len().checked_sub(1)?;
+
+        // Index of the keyframe that starts the segment containing `time`.
+        let i = match keyframes.iter().position(|keyframe| keyframe.time > time) {
+            Some(0) => return Some(keyframes[0].transform),
+            Some(index) => index - 1,
+            None => return Some(keyframes[last].transform),
+        };
+
+        let t = |index: usize| keyframes[index.min(last)].time;
+        let i0 = i.saturating_sub(1);
+        let i2 = (i + 1).min(last);
+        let i3 = (i + 2).min(last);
+
+        let mut p0 = to_array(&keyframes[i0].transform);
+        let p1 = to_array(&keyframes[i].transform);
+        let mut p2 = to_array(&keyframes[i2].transform);
+        let mut p3 = to_array(&keyframes[i3].transform);
+
+        // Make the yaws continuous so we never take the long way around.
+        p0[3] = nearest_equivalent_angle(Rad(p0[3]), Rad(p1[3])).0;
+        p2[3] = nearest_equivalent_angle(Rad(p2[3]), Rad(p1[3])).0;
+        p3[3] = nearest_equivalent_angle(Rad(p3[3]), Rad(p2[3])).0;
+
+        let h = t(i + 1) - t(i);
+        let s = (time - t(i)) / h;
+        let tangent = |a: f32, b: f32, ta: f32, tb: f32| {
+            if tb > ta {
+                (b - a) / (tb - ta)
+            } else {
+                0.0
+            }
+        };
+
+        let s2 = s * s;
+        let s3 = s2 * s;
+        let h00 = 2.0 * s3 - 3.0 * s2 + 1.0;
+        let h10 = s3 - 2.0 * s2 + s;
+        let h01 = -2.0 * s3 + 3.0 * s2;
+        let h11 = s3 - s2;
+
+        let mut result = [0.0; 6];
+        for k in 0..6 {
+            let m1 = tangent(p0[k], p2[k], t(i0), t(i2));
+            let m2 = tangent(p1[k], p3[k], t(i), t(i3));
+            result[k] = h00 * p1[k] + h10 * h * m1 + h01 * p2[k] + h11 * h * m2;
+        }
+
+        Some(from_array(result))
+    }
+
+    /// Writes one keyframe per line: time, position x, y and z, yaw, pitch
+    /// and vertical field of view, with angles in radians.
+    pub fn write<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        writeln!(writer, "# time x y z yaw pitch fovy")?;
+        for keyframe in self.keyframes.iter() {
+            let [x, y, z, yaw, pitch, fovy] = to_array(&keyframe.transform);
+            writeln!(
+                writer,
+                "{} {} {} {} {} {} {}",
+                keyframe.time, x, y, z, yaw, pitch, fovy
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Reads the format produced by `write`. Empty lines and lines starting
+    /// with `#` are ignored.
+    pub fn read<R: BufRead>(reader: R) -> Result<Self, CameraPathError> {
+        let mut path = CameraPath::new();
+        for (index, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let values = line
+                .split_whitespace()
+                .map(|word| word.parse::<f32>())
+                .collect::<Result<Vec<f32>, _>>()
+                .ok()
+                .filter(|values| values.len() == 7)
+                .ok_or(CameraPathError::Parse { line: index + 1 })?;
+
+            let time = values[0];
+            if path.keyframes.last().is_some_and(|last| last.time >= time) {
+                return Err(CameraPathError::Order { line: index + 1 });
+            }
+
+            path.keyframes.push(Keyframe {
+                time,
+                transform: from_array([
+                    values[1], values[2], values[3], values[4], values[5], values[6],
+                ]),
+            });
+        }
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transform(x: f32, yaw: f32) -> CameraTransform {
+        CameraTransform {
+            position: Point3::new(x, 0.0, 0.0),
+            yaw: Deg(yaw).into(),
+            pitch: Rad(0.0),
+            fovy: Deg(90.0).into(),
+        }
+    }
+
+    #[test]
+    fn sample_passes_through_keyframes_and_wraps_yaw() {
+        let mut path = CameraPath::new();
+        path.push(transform(0.0, 170.0), 0.0);
+        path.push(transform(1.0, -170.0), 1.0);
+        path.push(transform(3.0, -150.0), 2.0);
+
+        for keyframe in path.keyframes.iter() {
+            let sample = path.sample(keyframe.time).unwrap();
+            assert!((sample.position - keyframe.transform.position).magnitude() < 1e-5);
+        }
+
+        // Halfway between 170 and -170 degrees is 180, not 0.
+        let yaw = path.sample(0.5).unwrap().yaw;
+        let yaw = nearest_equivalent_angle(yaw, Deg(180.0).into());
+        assert!((Deg::from(yaw).0 - 180.0).abs() < 1.0);
+
+        let mut bytes = Vec::new();
+        path.write(&mut bytes).unwrap();
+        let read = CameraPath::read(&bytes[..]).unwrap();
+        assert_eq!(path.keyframes.len(), read.keyframes.len());
+        assert_eq!(3.0, read.duration());
+    }
+}
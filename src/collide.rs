@@ -0,0 +1,584 @@
+use crate::aabb::*;
+use crate::axis::*;
+use crate::bvh::*;
+use crate::vector::*;
+use cgmath::{Matrix4, SquareMatrix};
+
+#[inline]
+fn transform_point(transform: &Matrix4<f32>, point: Vector3<f32>) -> Vector3<f32> {
+    let p = transform * cgmath::Vector4::new(point.x, point.y, point.z, 1.0);
+    Vector3 { x: p.x, y: p.y, z: p.z }
+}
+
+#[inline]
+fn transform_aabb(transform: &Matrix4<f32>, aabb: AABB3) -> AABB3 {
+    AABB3::from_points(aabb.corners().iter().map(|&corner| transform_point(transform, corner))).unwrap()
+}
+
+/// Signed distances of the triangle's vertices to the plane through `plane`,
+/// with values within a small tolerance of the plane snapped to zero. Returns
+/// `None` when all vertices lie strictly on one side.
+fn plane_distances(plane: [Vector3<f32>; 3], triangle: [Vector3<f32>; 3]) -> Option<[f32; 3]> {
+    let normal = (plane[1] - plane[0]).cross(plane[2] - plane[0]);
+    let length = normal.magnitude();
+    let normal = normal / length;
+    let scale = triangle
+        .iter()
+        .chain(plane.iter())
+        .map(|&p| (p - plane[0]).magnitude())
+        .fold(0.0, f32::max);
+    let epsilon = scale * 1e-6;
+
+    let mut distances = [0.0; 3];
+    for (distance, &vertex) in distances.iter_mut().zip(triangle.iter()) {
+        let d = normal.dot(vertex - plane[0]);
+        *distance = if d.abs() < epsilon { 0.0 } else { d };
+    }
+
+    if distances.iter().all(|&d| d > 0.0) || distances.iter().all(|&d| d < 0.0) {
+        None
+    } else {
+        Some(distances)
+    }
+}
+
+/// Points where the triangle touches the plane its distances were computed
+/// against: edge crossings and vertices lying on the plane.
+fn plane_crossings(triangle: [Vector3<f32>; 3], distances: [f32; 3]) -> ([Vector3<f32>; 3], usize) {
+    let mut points = [Vector3::zero(); 3];
+    let mut count = 0;
+    for i in 0..3 {
+        let j = (i + 1) % 3;
+        let (di, dj) = (distances[i], distances[j]);
+        if di == 0.0 {
+            points[count] = triangle[i];
+            count += 1;
+        } else if (di < 0.0 && dj > 0.0) || (di > 0.0 && dj < 0.0) {
+            points[count] = triangle[i] + (triangle[j] - triangle[i]) * (di / (di - dj));
+            count += 1;
+        }
+        if count == 3 {
+            break;
+        }
+    }
+    (points, count)
+}
+
+fn coplanar_intersection(a: [Vector3<f32>; 3], b: [Vector3<f32>; 3]) -> Option<Vector3<f32>> {
+    // Project onto the plane most perpendicular to the normal.
+    let normal = (a[1] - a[0]).cross(a[2] - a[0]);
+    let abs_normal = Vector3 {
+        x: normal.x.abs(),
+        y: normal.y.abs(),
+        z: normal.z.abs(),
+    };
+    let (u, v) = match abs_normal.largest_component() {
+        Axis3::X => (Axis3::Y, Axis3::Z),
+        Axis3::Y => (Axis3::Z, Axis3::X),
+        Axis3::Z => (Axis3::X, Axis3::Y),
+    };
+
+    let perp = |o: Vector3<f32>, p: Vector3<f32>, q: Vector3<f32>| -> f32 {
+        (p[u] - o[u]) * (q[v] - o[v]) - (p[v] - o[v]) * (q[u] - o[u])
+    };
+
+    // Edge versus edge.
+    for i in 0..3 {
+        let (p0, p1) = (a[i], a[(i + 1) % 3]);
+        for j in 0..3 {
+            let (q0, q1) = (b[j], b[(j + 1) % 3]);
+            let d0 = perp(p0, p1, q0);
+            let d1 = perp(p0, p1, q1);
+            let d2 = perp(q0, q1, p0);
+            let d3 = perp(q0, q1, p1);
+            if d0 * d1 <= 0.0 && d2 * d3 <= 0.0 && d0 != d1 {
+                return Some(q0 + (q1 - q0) * (d0 / (d0 - d1)));
+            }
+        }
+    }
+
+    // One triangle completely inside the other.
+    let inside = |t: [Vector3<f32>; 3], p: Vector3<f32>| -> bool {
+        let e0 = perp(t[0], t[1], p);
+        let e1 = perp(t[1], t[2], p);
+        let e2 = perp(t[2], t[0], p);
+        (e0 >= 0.0 && e1 >= 0.0 && e2 >= 0.0) || (e0 <= 0.0 && e1 <= 0.0 && e2 <= 0.0)
+    };
+    if inside(b, a[0]) {
+        Some(a[0])
+    } else if inside(a, b[0]) {
+        Some(b[0])
+    } else {
+        None
+    }
+}
+
+/// Exact triangle versus triangle test after Möller (1997). Returns the end
+/// points of the intersection segment, or a single point twice when the
+/// triangles are coplanar and overlap. Degenerate triangles never intersect.
+pub fn triangle_versus_triangle(
+    a: [Vector3<f32>; 3],
+    b: [Vector3<f32>; 3],
+) -> Option<[Vector3<f32>; 2]> {
+    let normal_a = (a[1] - a[0]).cross(a[2] - a[0]);
+    let normal_b = (b[1] - b[0]).cross(b[2] - b[0]);
+    if normal_a.magnitude2() == 0.0 || normal_b.magnitude2() == 0.0 {
+        return None;
+    }
+
+    let da = plane_distances(b, a)?;
+    let db = plane_distances(a, b)?;
+
+    if da.iter().all(|&d| d == 0.0) || db.iter().all(|&d| d == 0.0) {
+        return coplanar_intersection(a, b).map(|p| [p, p]);
+    }
+
+    // Both triangles cross the line where their planes meet. Intersect the
+    // intervals they cover along that line.
+    let direction = normal_a.cross(normal_b);
+    let interval = |triangle, distances| -> ((f32, Vector3<f32>), (f32, Vector3<f32>)) {
+        let (points, count) = plane_crossings(triangle, distances);
+        let mut lo = (f32::INFINITY, Vector3::zero());
+        let mut hi = (-f32::INFINITY, Vector3::zero());
+        for &point in points[..count].iter() {
+            let s = direction.dot(point);
+            if s < lo.0 {
+                lo = (s, point);
+            }
+            if s > hi.0 {
+                hi = (s, point);
+            }
+        }
+        (lo, hi)
+    };
+
+    let (lo_a, hi_a) = interval(a, da);
+    let (lo_b, hi_b) = interval(b, db);
+
+    let lo = if lo_a.0 > lo_b.0 { lo_a } else { lo_b };
+    let hi = if hi_a.0 < hi_b.0 { hi_a } else { hi_b };
+
+    if lo.0 <= hi.0 {
+        Some([lo.1, hi.1])
+    } else {
+        None
+    }
+}
+
+/// Whether an intersection `segment` of two triangles lies entirely on the
+/// vertices they share by position: on the single shared vertex, or on the
+/// shared edge. Coplanar triangles report a single point, for those the test
+/// is repeated with both triangles shrunk slightly towards their centroids
+/// so that only overlapping areas count.
+fn touches_only_at_shared_vertices(a: [Vector3<f32>; 3], b: [Vector3<f32>; 3], segment: [Vector3<f32>; 2]) -> bool {
+    let mut shared = [Vector3::zero(); 3];
+    let mut shared_count = 0;
+    for &p in a.iter() {
+        if b.contains(&p) {
+            shared[shared_count] = p;
+            shared_count += 1;
+        }
+    }
+
+    let scale = a
+        .iter()
+        .chain(b.iter())
+        .map(|&p| (p - a[0]).magnitude())
+        .fold(0.0, f32::max);
+    let epsilon = scale * 1e-5;
+
+    let on_shared = |p: Vector3<f32>| match shared_count {
+        1 => (p - shared[0]).magnitude() <= epsilon,
+        2 => {
+            let edge = shared[1] - shared[0];
+            let t = ((p - shared[0]).dot(edge) / edge.magnitude2()).clamp(0.0, 1.0);
+            (shared[0] + edge * t - p).magnitude() <= epsilon
+        }
+        _ => false,
+    };
+
+    match shared_count {
+        0 => false,
+        // Duplicated faces.
+        3 => true,
+        _ if segment[0] == segment[1] => {
+            let shrink = |t: [Vector3<f32>; 3]| {
+                let centroid = (t[0] + t[1] + t[2]) / 3.0;
+                t.map(|p| p + (centroid - p) * 1e-3)
+            };
+            triangle_versus_triangle(shrink(a), shrink(b)).is_none()
+        }
+        _ => on_shared(segment[0]) && on_shared(segment[1]),
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct SelfIntersection {
+    /// Indices into `Tree::triangles`.
+    pub triangles: (u32, u32),
+    /// End points of the intersection segment.
+    pub segment: [Vector3<f32>; 2],
+}
+
+impl<'a> TreeView<'a> {
+    /// Visits the pairs of leaves whose bounds overlap when `other` is placed
+    /// in the space of `self` by `other_to_self`. Descends into the larger of
+    /// the two nodes first. Stops as soon as `visit` returns false.
+    ///
+    /// With `self_pairs`, `other` must be `self` and `other_to_self` is
+    /// ignored. Every unordered pair of leaves is visited once, including
+    /// each leaf paired with itself.
+    fn visit_overlapping_leaves<F>(
+        &self,
+        other: &TreeView,
+        other_to_self: &Matrix4<f32>,
+        self_pairs: bool,
+        mut visit: F,
+    ) where
+        F: FnMut(&Node, &Node) -> bool,
+    {
+        if self.triangles.is_empty() || other.triangles.is_empty() {
+            return;
+        }
+
+        let mut stack = vec![(0u32, 0u32)];
+
+        while let Some((a_index, b_index)) = stack.pop() {
+            let a = &self.nodes[a_index as usize];
+            let b = &other.nodes[b_index as usize];
+
+            if self_pairs && a_index == b_index {
+                if a.is_branch() {
+                    let left = a.left_or_offset;
+                    stack.push((left, left));
+                    stack.push((left + 1, left + 1));
+                    stack.push((left, left + 1));
+                } else if !visit(a, a) {
+                    return;
+                }
+                continue;
+            }
+
+            let a_aabb = a.aabb();
+            let b_aabb = if self_pairs {
+                b.aabb()
+            } else {
+                transform_aabb(other_to_self, b.aabb())
+            };
+
+            if !a_aabb.intersects(&b_aabb) {
+                continue;
+            }
+
+            let descend_a = match (a.is_branch(), b.is_branch()) {
+                (false, false) => {
+                    if !visit(a, b) {
+                        return;
+                    }
+                    continue;
+                }
+                (true, false) => true,
+                (false, true) => false,
+                (true, true) => a_aabb.surface_area() >= b_aabb.surface_area(),
+            };
+
+            if descend_a {
+                stack.push((a.left_or_offset, b_index));
+                stack.push((a.left_or_offset + 1, b_index));
+            } else {
+                stack.push((a_index, b.left_or_offset));
+                stack.push((a_index, b.left_or_offset + 1));
+            }
+        }
+    }
+
+    /// Visits the pairs of intersecting triangles of `self` and `other` by
+    /// their indices into `self.triangles` and `other.triangles`. Stops as
+    /// soon as `visit` returns false.
+    fn visit_intersecting_triangles<F>(
+        &self,
+        vertices: &[Vector3<f32>],
+        other: &TreeView,
+        other_vertices: &[Vector3<f32>],
+        other_to_self: &Matrix4<f32>,
+        mut visit: F,
+    ) where
+        F: FnMut(u32, u32) -> bool,
+    {
+        self.visit_overlapping_leaves(other, other_to_self, false, |a, b| {
+            for a_index in a.left_or_offset..a.left_or_offset + a.count {
+                let a_triangle = self.triangle_vertices(vertices, a_index);
+                for b_index in b.left_or_offset..b.left_or_offset + b.count {
+                    let b_triangle = other
+                        .triangle_vertices(other_vertices, b_index)
+                        .map(|p| transform_point(other_to_self, p));
+                    if triangle_versus_triangle(a_triangle, b_triangle).is_some() && !visit(a_index, b_index) {
+                        return false;
+                    }
+                }
+            }
+            true
+        });
+    }
+
+    /// Returns every pair of intersecting triangles as indices into
+    /// `self.triangles` and `other.triangles`. The rigid transform
+    /// `other_to_self` maps the vertices of `other` into the space of `self`.
+    pub fn intersecting_triangles(
+        &self,
+        vertices: &[Vector3<f32>],
+        other: &TreeView,
+        other_vertices: &[Vector3<f32>],
+        other_to_self: &Matrix4<f32>,
+    ) -> Vec<(u32, u32)> {
+        let mut pairs = Vec::new();
+        self.visit_intersecting_triangles(vertices, other, other_vertices, other_to_self, |a_index, b_index| {
+            pairs.push((a_index, b_index));
+            true
+        });
+        pairs
+    }
+
+    /// Finds pairs of triangles in the mesh that intersect each other.
+    /// Triangles that share vertices, either by index or by position, are
+    /// only reported when they intersect away from the shared vertex or edge,
+    /// so folds through a shared vertex are found while ordinary neighbors
+    /// are not.
+    pub fn self_intersections(&self, vertices: &[Vector3<f32>]) -> Vec<SelfIntersection> {
+        let mut intersections = Vec::new();
+
+        let mut test = |a_index: u32, b_index: u32| {
+            let a = self.triangle_vertices(vertices, a_index);
+            let b = self.triangle_vertices(vertices, b_index);
+            if let Some(segment) = triangle_versus_triangle(a, b) {
+                if !touches_only_at_shared_vertices(a, b, segment) {
+                    intersections.push(SelfIntersection {
+                        triangles: (a_index, b_index),
+                        segment,
+                    });
+                }
+            }
+        };
+
+        self.visit_overlapping_leaves(self, &Matrix4::identity(), true, |a, b| {
+            let a_range = a.left_or_offset..a.left_or_offset + a.count;
+            for i in a_range.clone() {
+                if std::ptr::eq(a, b) {
+                    for j in i + 1..a_range.end {
+                        test(i, j);
+                    }
+                } else {
+                    for j in b.left_or_offset..b.left_or_offset + b.count {
+                        test(i, j);
+                    }
+                }
+            }
+            true
+        });
+
+        intersections
+    }
+
+    /// Like `intersecting_triangles` but stops at the first intersection.
+    pub fn intersects(
+        &self,
+        vertices: &[Vector3<f32>],
+        other: &TreeView,
+        other_vertices: &[Vector3<f32>],
+        other_to_self: &Matrix4<f32>,
+    ) -> bool {
+        let mut found = false;
+        self.visit_intersecting_triangles(vertices, other, other_vertices, other_to_self, |_, _| {
+            found = true;
+            false
+        });
+        found
+    }
+}
+
+impl Tree {
+    #[inline]
+    pub fn intersecting_triangles(
+        &self,
+        vertices: &[Vector3<f32>],
+        other: &Tree,
+        other_vertices: &[Vector3<f32>],
+        other_to_self: &Matrix4<f32>,
+    ) -> Vec<(u32, u32)> {
+        self.view()
+            .intersecting_triangles(vertices, &other.view(), other_vertices, other_to_self)
+    }
+
+    #[inline]
+    pub fn self_intersections(&self, vertices: &[Vector3<f32>]) -> Vec<SelfIntersection> {
+        self.view().self_intersections(vertices)
+    }
+
+    #[inline]
+    pub fn intersects(
+        &self,
+        vertices: &[Vector3<f32>],
+        other: &Tree,
+        other_vertices: &[Vector3<f32>],
+        other_to_self: &Matrix4<f32>,
+    ) -> bool {
+        self.view()
+            .intersects(vertices, &other.view(), other_vertices, other_to_self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    #[test]
+    fn crossing_triangles() {
+        let a = [v(0.0, 0.0, 0.0), v(2.0, 0.0, 0.0), v(0.0, 2.0, 0.0)];
+        let b = [v(0.5, 0.5, -1.0), v(0.5, 0.5, 1.0), v(3.0, 0.5, 0.0)];
+        let [p, q] = triangle_versus_triangle(a, b).unwrap();
+        assert!(p.z.abs() < 1e-6 && q.z.abs() < 1e-6);
+        assert!((p.y - 0.5).abs() < 1e-6 && (q.y - 0.5).abs() < 1e-6);
+
+        let c = [v(0.0, 0.0, 1.0), v(2.0, 0.0, 1.0), v(0.0, 2.0, 1.0)];
+        assert!(triangle_versus_triangle(a, c).is_none());
+
+        let d = [v(0.25, 0.25, 0.0), v(0.5, 0.25, 0.0), v(0.25, 0.5, 0.0)];
+        assert!(triangle_versus_triangle(a, d).is_some());
+    }
+
+    #[test]
+    fn tree_versus_translated_tree() {
+        let vertices = vec![v(0.0, 0.0, 0.0), v(1.0, 0.0, 0.0), v(0.0, 1.0, 0.0), v(0.2, 0.2, -0.5)];
+        let triangles = vec![[0, 1, 2], [0, 1, 3]];
+        let tree = Tree::new(&vertices, &triangles);
+
+        let identity = Matrix4::identity();
+        assert!(tree.intersects(&vertices, &tree, &vertices, &identity));
+
+        let far = Matrix4::from_translation(cgmath::Vector3::new(5.0, 0.0, 0.0));
+        assert!(!tree.intersects(&vertices, &tree, &vertices, &far));
+        assert!(tree.intersecting_triangles(&vertices, &tree, &vertices, &far).is_empty());
+    }
+
+    #[test]
+    fn grid_versus_rotated_needles() {
+        // A 6 by 6 grid of unit quads in the z = 0 plane, 72 triangles.
+        let mut grid_vertices = Vec::new();
+        for j in 0..7 {
+            for i in 0..7 {
+                grid_vertices.push(v(i as f32, j as f32, 0.0));
+            }
+        }
+        let mut grid_triangles = Vec::new();
+        for j in 0..6u32 {
+            for i in 0..6u32 {
+                let a = j * 7 + i;
+                grid_triangles.push([a, a + 1, a + 8]);
+                grid_triangles.push([a, a + 8, a + 7]);
+            }
+        }
+        let grid = Tree::new(&grid_vertices, &grid_triangles);
+
+        // Vertical needles along the x axis that cross z = 0 between x - 0.05
+        // and x + 0.05.
+        let mut needle_vertices = Vec::new();
+        let mut needle_triangles = Vec::new();
+        for k in 0..10u32 {
+            let x = k as f32;
+            needle_vertices.extend_from_slice(&[v(x, 0.0, -1.0), v(x + 0.1, 0.0, 1.0), v(x - 0.1, 0.0, 1.0)]);
+            needle_triangles.push([k * 3, k * 3 + 1, k * 3 + 2]);
+        }
+        let needles = Tree::new(&needle_vertices, &needle_triangles);
+
+        // Turned to run along y at x = 2.7, needle k pierces the lower right
+        // triangle of quad (2, k). Needles 6 to 9 are past the grid.
+        let needles_to_grid = Matrix4::from_translation(cgmath::Vector3::new(2.7, 0.3, 0.0))
+            * Matrix4::from_angle_z(cgmath::Deg(90.0));
+        let expected = |upper_left: usize| {
+            let mut pairs: Vec<(Triangle, Triangle)> = (0..6)
+                .map(|k| (grid_triangles[(k * 6 + 2) * 2 + upper_left], needle_triangles[k]))
+                .collect();
+            pairs.sort_unstable();
+            pairs
+        };
+        let actual = |needles_to_grid: &Matrix4<f32>| {
+            let mut pairs: Vec<(Triangle, Triangle)> = grid
+                .intersecting_triangles(&grid_vertices, &needles, &needle_vertices, needles_to_grid)
+                .into_iter()
+                .map(|(a, b)| (grid.triangles[a as usize], needles.triangles[b as usize]))
+                .collect();
+            pairs.sort_unstable();
+            pairs
+        };
+        assert_eq!(expected(0), actual(&needles_to_grid));
+        assert!(grid.intersects(&grid_vertices, &needles, &needle_vertices, &needles_to_grid));
+
+        // Moved to x = 2.3 and y = k + 0.7, the needles pierce the upper left
+        // triangles instead.
+        let shifted = Matrix4::from_translation(cgmath::Vector3::new(-0.4, 0.4, 0.0)) * needles_to_grid;
+        assert_eq!(expected(1), actual(&shifted));
+
+        let beside = Matrix4::from_translation(cgmath::Vector3::new(10.0, 0.0, 0.0)) * needles_to_grid;
+        assert!(!grid.intersects(&grid_vertices, &needles, &needle_vertices, &beside));
+    }
+
+    #[test]
+    fn self_intersections_skip_adjacent_triangles() {
+        let vertices = vec![
+            v(0.0, 0.0, 0.0),
+            v(2.0, 0.0, 0.0),
+            v(0.0, 2.0, 0.0),
+            v(2.0, 2.0, 0.0),
+            // Unwelded copy of vertex 1, adjacent by position only.
+            v(2.0, 0.0, 0.0),
+            v(0.5, 0.5, -1.0),
+            v(0.5, 0.5, 1.0),
+            v(3.0, 0.5, 0.0),
+        ];
+        let triangles = vec![[0, 1, 2], [4, 3, 2], [5, 6, 7]];
+        let tree = Tree::new(&vertices, &triangles);
+
+        let intersections = tree.self_intersections(&vertices);
+        assert_eq!(2, intersections.len());
+        for intersection in intersections.iter() {
+            let (a, b) = intersection.triangles;
+            let pair = [tree.triangles[a as usize], tree.triangles[b as usize]];
+            assert!(pair.contains(&[5, 6, 7]));
+        }
+    }
+
+    #[test]
+    fn self_intersections_through_shared_vertices() {
+        let vertices = vec![
+            v(0.0, 0.0, 0.0),
+            v(2.0, 0.0, 0.0),
+            v(0.0, 2.0, 0.0),
+            // Folds back through vertex 0 and pierces the first triangle.
+            v(1.0, 0.5, -1.0),
+            v(0.5, 1.0, 1.0),
+            // Only touches vertex 0.
+            v(-1.0, 0.0, 1.0),
+            v(0.0, -1.0, 1.0),
+            // Coplanar, overlapping across the shared edge 0-1.
+            v(1.5, 0.25, 0.0),
+            // Coplanar neighbor across the shared edge 1-2.
+            v(2.0, 2.0, 0.0),
+        ];
+        let triangles = vec![[0, 1, 2], [0, 3, 4], [0, 5, 6], [0, 1, 7], [1, 8, 2]];
+        let tree = Tree::new(&vertices, &triangles);
+
+        let mut pairs: Vec<[Triangle; 2]> = tree
+            .self_intersections(&vertices)
+            .iter()
+            .map(|intersection| {
+                let (a, b) = intersection.triangles;
+                let mut pair = [tree.triangles[a as usize], tree.triangles[b as usize]];
+                pair.sort_unstable();
+                pair
+            })
+            .collect();
+        pairs.sort_unstable();
+        assert_eq!(vec![[[0, 1, 2], [0, 1, 7]], [[0, 1, 2], [0, 3, 4]]], pairs);
+    }
+}
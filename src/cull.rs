@@ -0,0 +1,264 @@
+use cgmath::*;
+
+use crate::aabb::*;
+use crate::bvh::*;
+
+/// The half space of points `p` with `normal.dot(p) + distance >= 0`.
+#[derive(Debug, Copy, Clone)]
+pub struct Plane3<T> {
+    pub normal: Vector3<T>,
+    pub distance: T,
+}
+
+impl<T: BaseFloat> Plane3<T> {
+    #[inline]
+    pub fn new(normal: Vector3<T>, distance: T) -> Self {
+        Self { normal, distance }
+    }
+
+    #[inline]
+    pub fn signed_distance(&self, point: Point3<T>) -> T {
+        self.normal.dot(point.to_vec()) + self.distance
+    }
+
+    /// Expresses a plane defined in some space in the space of its parent,
+    /// given the matrix that transforms positions from the parent into the
+    /// plane's space.
+    #[inline]
+    pub fn transform(&self, pos_from_parent: &Matrix4<T>) -> Self {
+        let p = pos_from_parent.transpose() * self.normal.extend(self.distance);
+        Self {
+            normal: p.truncate(),
+            distance: p.w,
+        }
+    }
+
+    #[inline]
+    pub fn cast<U: BaseFloat>(&self) -> Option<Plane3<U>> {
+        Some(Plane3 {
+            normal: self.normal.cast()?,
+            distance: num_traits::cast(self.distance)?,
+        })
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Containment {
+    Outside,
+    Intersecting,
+    Inside,
+}
+
+/// The six planes bounding a view volume, with their normals pointing inwards.
+#[derive(Debug, Copy, Clone)]
+pub struct FrustumPlanes<T> {
+    pub planes: [Plane3<T>; 6],
+}
+
+impl<T: BaseFloat> FrustumPlanes<T> {
+    #[inline]
+    pub fn transform(&self, pos_from_parent: &Matrix4<T>) -> Self {
+        let mut planes = self.planes;
+        for plane in planes.iter_mut() {
+            *plane = plane.transform(pos_from_parent);
+        }
+        Self { planes }
+    }
+
+    #[inline]
+    pub fn cast<U: BaseFloat>(&self) -> Option<FrustumPlanes<U>> {
+        let p = &self.planes;
+        Some(FrustumPlanes {
+            planes: [
+                p[0].cast()?,
+                p[1].cast()?,
+                p[2].cast()?,
+                p[3].cast()?,
+                p[4].cast()?,
+                p[5].cast()?,
+            ],
+        })
+    }
+}
+
+impl FrustumPlanes<f32> {
+    pub fn classify(&self, aabb: &AABB3) -> Containment {
+        let min: [f32; 3] = aabb.min.into();
+        let max: [f32; 3] = aabb.max.into();
+        let center = (Vector3::from(min) + Vector3::from(max)) * 0.5;
+        let extent = (Vector3::from(max) - Vector3::from(min)) * 0.5;
+
+        let mut containment = Containment::Inside;
+        for plane in self.planes.iter() {
+            let n = plane.normal;
+            let s = n.dot(center) + plane.distance;
+            let r = n.x.abs() * extent.x + n.y.abs() * extent.y + n.z.abs() * extent.z;
+            if s + r < 0.0 {
+                return Containment::Outside;
+            }
+            if s - r < 0.0 {
+                containment = Containment::Intersecting;
+            }
+        }
+        containment
+    }
+}
+
+impl<'a> TreeView<'a> {
+    /// Appends the indices of all leaf nodes that are at least partially
+    /// inside the frustum. Subtrees that are completely inside are accepted
+    /// without testing their descendants.
+    pub fn visible_leaves(&self, planes: &FrustumPlanes<f32>, leaves: &mut Vec<u32>) {
+        if self.triangles.is_empty() {
+            return;
+        }
+
+        let mut stack = vec![(0u32, false)];
+
+        while let Some((node_index, inside)) = stack.pop() {
+            let node = &self.nodes[node_index as usize];
+
+            let inside = inside
+                || match planes.classify(&node.aabb()) {
+                    Containment::Outside => continue,
+                    Containment::Intersecting => false,
+                    Containment::Inside => true,
+                };
+
+            if node.is_branch() {
+                stack.push((node.left_or_offset, inside));
+                stack.push((node.left_or_offset + 1, inside));
+            } else {
+                leaves.push(node_index);
+            }
+        }
+    }
+}
+
+impl Tree {
+    #[inline]
+    pub fn visible_leaves(&self, planes: &FrustumPlanes<f32>, leaves: &mut Vec<u32>) {
+        self.view().visible_leaves(planes, leaves)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frustum::*;
+
+    fn aabb(min: [f32; 3], max: [f32; 3]) -> AABB3 {
+        AABB3 {
+            min: min.into(),
+            max: max.into(),
+        }
+    }
+
+    #[test]
+    fn classify_against_perspective_frustum() {
+        let frustum = Frustum3 {
+            x0: -1.0,
+            x1: 1.0,
+            y0: -1.0,
+            y1: 1.0,
+            z0: -100.0,
+            z1: -0.1,
+        };
+        let planes = frustum.perspective_planes(&Matrix4::identity());
+
+        let inside = aabb([-1.0, -1.0, -6.0], [1.0, 1.0, -4.0]);
+        let behind = aabb([-1.0, -1.0, 4.0], [1.0, 1.0, 6.0]);
+        let straddling = aabb([4.0, -1.0, -6.0], [6.0, 1.0, -4.0]);
+        assert_eq!(Containment::Inside, planes.classify(&inside));
+        assert_eq!(Containment::Outside, planes.classify(&behind));
+        assert_eq!(Containment::Intersecting, planes.classify(&straddling));
+
+        // Turn the camera around, it now sees what was behind it.
+        let pos_from_parent = Matrix4::from_angle_y(Deg(180.0));
+        let planes = frustum.perspective_planes(&pos_from_parent);
+        assert_eq!(Containment::Outside, planes.classify(&inside));
+        assert_eq!(Containment::Inside, planes.classify(&behind));
+    }
+
+    /// One small triangle at every integer x from 0 to 63, in the z = -5 plane.
+    fn triangle_row() -> Tree {
+        let v = |x, y| crate::vector::Vector3 { x, y, z: -5.0 };
+        let mut vertices = Vec::new();
+        let mut triangles = Vec::new();
+        for i in 0..64 {
+            let x = i as f32;
+            let base = vertices.len() as u32;
+            vertices.extend_from_slice(&[v(x, 0.0), v(x + 0.5, 0.0), v(x, 0.5)]);
+            triangles.push([base, base + 1, base + 2]);
+        }
+        Tree::new(&vertices, &triangles)
+    }
+
+    fn box_planes(x0: f32, x1: f32, pos_from_parent: &Matrix4<f32>) -> FrustumPlanes<f32> {
+        let frustum = Frustum3 {
+            x0,
+            x1,
+            y0: -1.0,
+            y1: 1.0,
+            z0: -10.0,
+            z1: -1.0,
+        };
+        frustum.orthographic_planes(pos_from_parent)
+    }
+
+    fn sorted_visible_leaves(tree: &Tree, planes: &FrustumPlanes<f32>) -> Vec<u32> {
+        let mut leaves = Vec::new();
+        tree.visible_leaves(planes, &mut leaves);
+        leaves.sort_unstable();
+        leaves
+    }
+
+    #[test]
+    fn visible_leaves_of_multi_leaf_tree() {
+        let tree = triangle_row();
+        let all_leaves: Vec<u32> = (0..tree.nodes.len() as u32)
+            .filter(|&i| i != 1 && !tree.nodes[i as usize].is_branch())
+            .collect();
+        assert!(all_leaves.len() > 2);
+
+        // Partially visible: exactly the leaves that are not outside, which
+        // between them hold the triangles from 11 to 20.
+        let planes = box_planes(10.75, 20.25, &Matrix4::identity());
+        let leaves = sorted_visible_leaves(&tree, &planes);
+        let expected: Vec<u32> = all_leaves
+            .iter()
+            .copied()
+            .filter(|&i| planes.classify(&tree.nodes[i as usize].aabb()) != Containment::Outside)
+            .collect();
+        assert_eq!(expected, leaves);
+        assert!(leaves.len() < all_leaves.len());
+        // Triangle x uses the vertices from 3 * x.
+        let mut visible_xs: Vec<u32> = leaves
+            .iter()
+            .flat_map(|&i| tree.leaf_triangles(&tree.nodes[i as usize]).iter())
+            .map(|t| t[0] / 3)
+            .collect();
+        visible_xs.sort_unstable();
+        for x in 11..=20 {
+            assert!(visible_xs.contains(&x));
+        }
+
+        // Everything visible: the root is inside, so every leaf is accepted
+        // without classifying it. Moving a leaf's bounds out of view in a
+        // copy of the tree shows that it is not tested.
+        let planes = box_planes(-1.0, 65.0, &Matrix4::identity());
+        assert_eq!(Containment::Inside, planes.classify(&tree.nodes[0].aabb()));
+        assert_eq!(all_leaves, sorted_visible_leaves(&tree, &planes));
+        let mut moved = Tree {
+            nodes: tree.nodes.clone(),
+            triangles: tree.triangles.clone(),
+        };
+        moved.nodes[all_leaves[0] as usize].min.x = 1000.0;
+        moved.nodes[all_leaves[0] as usize].max.x = 1001.0;
+        assert_eq!(all_leaves, sorted_visible_leaves(&moved, &planes));
+
+        // Nothing visible: the same box looking the other way.
+        let planes = box_planes(-1.0, 65.0, &Matrix4::from_angle_y(Deg(180.0)));
+        assert!(sorted_visible_leaves(&tree, &planes).is_empty());
+    }
+}
@@ -0,0 +1,677 @@
+use crate::bvh::*;
+use crate::vector::*;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+pub fn closest_point_on_triangle(point: Vector3<f32>, triangle: [Vector3<f32>; 3]) -> Vector3<f32> {
+    let [a, b, c] = triangle;
+    let ab = b - a;
+    let ac = c - a;
+
+    // Vertex region A.
+    let ap = point - a;
+    let d1 = ab.dot(ap);
+    let d2 = ac.dot(ap);
+    if d1 <= 0.0 && d2 <= 0.0 {
+        return a;
+    }
+
+    // Vertex region B.
+    let bp = point - b;
+    let d3 = ab.dot(bp);
+    let d4 = ac.dot(bp);
+    if d3 >= 0.0 && d4 <= d3 {
+        return b;
+    }
+
+    // Edge region AB.
+    let vc = d1 * d4 - d3 * d2;
+    if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+        return a + ab * (d1 / (d1 - d3));
+    }
+
+    // Vertex region C.
+    let cp = point - c;
+    let d5 = ab.dot(cp);
+    let d6 = ac.dot(cp);
+    if d6 >= 0.0 && d5 <= d6 {
+        return c;
+    }
+
+    // Edge region AC.
+    let vb = d5 * d2 - d1 * d6;
+    if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+        return a + ac * (d2 / (d2 - d6));
+    }
+
+    // Edge region BC.
+    let va = d3 * d6 - d5 * d4;
+    if va <= 0.0 && (d4 - d3) >= 0.0 && (d5 - d6) >= 0.0 {
+        return b + (c - b) * ((d4 - d3) / ((d4 - d3) + (d5 - d6)));
+    }
+
+    // Face region.
+    let denom = 1.0 / (va + vb + vc);
+    a + ab * (vb * denom) + ac * (vc * denom)
+}
+
+/// Signed solid angle subtended by the triangle as seen from the point
+/// (Van Oosterom and Strackee). Positive when the point lies behind a
+/// counter-clockwise triangle.
+pub fn solid_angle(point: Vector3<f32>, triangle: [Vector3<f32>; 3]) -> f32 {
+    let a = triangle[0] - point;
+    let b = triangle[1] - point;
+    let c = triangle[2] - point;
+    let la = a.magnitude();
+    let lb = b.magnitude();
+    let lc = c.magnitude();
+    let numerator = a.dot(b.cross(c));
+    let denominator = la * lb * lc + a.dot(b) * lc + b.dot(c) * la + c.dot(a) * lb;
+    2.0 * numerator.atan2(denominator)
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct ClosestPoint {
+    /// Index into `Tree::triangles`.
+    pub triangle_index: u32,
+    pub point: Vector3<f32>,
+    pub distance2: f32,
+}
+
+impl<'a> TreeView<'a> {
+    pub fn closest_point(&self, vertices: &[Vector3<f32>], point: Vector3<f32>) -> Option<ClosestPoint> {
+        let mut closest: Option<ClosestPoint> = None;
+        let mut closest_d2 = f32::INFINITY;
+        let mut stack = vec![0u32];
+
+        while let Some(node_index) = stack.pop() {
+            let node = &self.nodes[node_index as usize];
+            if node.aabb().distance2_to_point(point) > closest_d2 {
+                continue;
+            }
+
+            if node.is_branch() {
+                let left = node.left_or_offset;
+                let right = left + 1;
+                let left_d2 = self.nodes[left as usize].aabb().distance2_to_point(point);
+                let right_d2 = self.nodes[right as usize].aabb().distance2_to_point(point);
+                // Push the farther child first so the nearer one is visited first.
+                if left_d2 < right_d2 {
+                    stack.push(right);
+                    stack.push(left);
+                } else {
+                    stack.push(left);
+                    stack.push(right);
+                }
+            } else {
+                for triangle_index in node.left_or_offset..node.left_or_offset + node.count {
+                    let triangle = self.triangle_vertices(vertices, triangle_index);
+                    let candidate = closest_point_on_triangle(point, triangle);
+                    let d2 = (candidate - point).magnitude2();
+                    if d2 < closest_d2 {
+                        closest_d2 = d2;
+                        closest = Some(ClosestPoint {
+                            triangle_index,
+                            point: candidate,
+                            distance2: d2,
+                        });
+                    }
+                }
+            }
+        }
+
+        closest
+    }
+}
+
+/// Nodes farther away than this multiple of their radius contribute to the
+/// winding number through their dipole. Keeps the error at a few hundredths,
+/// well clear of the 0.5 threshold used by `contains`.
+const FAR_FIELD_RATIO: f32 = 2.0;
+
+/// First order far field expansion of the triangles below a node: their
+/// summed area vectors placed at their area weighted centroid, and the
+/// radius of a sphere around that centroid containing them.
+#[derive(Debug, Copy, Clone)]
+struct Dipole {
+    center: Vector3<f32>,
+    area: f32,
+    area_vector: Vector3<f32>,
+    radius: f32,
+}
+
+impl Dipole {
+    fn zero() -> Self {
+        Dipole {
+            center: Vector3::zero(),
+            area: 0.0,
+            area_vector: Vector3::zero(),
+            radius: 0.0,
+        }
+    }
+}
+
+#[inline]
+fn is_finite(v: Vector3<f32>) -> bool {
+    v.x.is_finite() && v.y.is_finite() && v.z.is_finite()
+}
+
+/// Generalized winding numbers of a mesh (Jacobson et al. 2013), close to 1
+/// inside and 0 outside a closed, outward facing mesh, and degrading
+/// gracefully for meshes with small holes, non-manifold edges or duplicated
+/// faces.
+///
+/// Evaluated with the hierarchical approximation of Barill et al. 2018:
+/// subtrees that are far away relative to their size contribute through a
+/// dipole, only nearby leaves are summed exactly. Building is linear in the
+/// number of triangles, queries are roughly logarithmic.
+#[derive(Debug)]
+pub struct WindingNumbers<'a> {
+    tree: TreeView<'a>,
+    vertices: &'a [Vector3<f32>],
+    dipoles: Vec<Dipole>,
+}
+
+impl<'a> WindingNumbers<'a> {
+    pub fn new(tree: TreeView<'a>, vertices: &'a [Vector3<f32>]) -> Self {
+        let mut dipoles = vec![Dipole::zero(); tree.nodes.len()];
+
+        // Children always come after their parent.
+        for node_index in (0..tree.nodes.len()).rev() {
+            let node = &tree.nodes[node_index];
+            dipoles[node_index] = if node.is_branch() {
+                let a = dipoles[node.left_or_offset as usize];
+                let b = dipoles[node.left_or_offset as usize + 1];
+                let area = a.area + b.area;
+                let center = if area > 0.0 {
+                    (a.center * a.area + b.center * b.area) / area
+                } else {
+                    (a.center + b.center) * 0.5
+                };
+                Dipole {
+                    center,
+                    area,
+                    area_vector: a.area_vector + b.area_vector,
+                    radius: f32::max(
+                        (a.center - center).magnitude() + a.radius,
+                        (b.center - center).magnitude() + b.radius,
+                    ),
+                }
+            } else {
+                let triangles = tree
+                    .leaf_triangles(node)
+                    .iter()
+                    .map(|t| [vertices[t[0] as usize], vertices[t[1] as usize], vertices[t[2] as usize]])
+                    .filter(|triangle| triangle.iter().all(|&p| is_finite(p)));
+
+                let mut dipole = Dipole::zero();
+                let mut centroid_sum = Vector3::zero();
+                let mut point_sum = Vector3::zero();
+                let mut point_count = 0;
+                for [p0, p1, p2] in triangles.clone() {
+                    let area_vector = (p1 - p0).cross(p2 - p0) * 0.5;
+                    let area = area_vector.magnitude();
+                    dipole.area += area;
+                    dipole.area_vector += area_vector;
+                    centroid_sum += (p0 + p1 + p2) * (area / 3.0);
+                    point_sum += p0 + p1 + p2;
+                    point_count += 3;
+                }
+                if dipole.area > 0.0 {
+                    dipole.center = centroid_sum / dipole.area;
+                } else if point_count > 0 {
+                    dipole.center = point_sum / point_count as f32;
+                }
+                dipole.radius = triangles
+                    .flat_map(|triangle| triangle.to_vec())
+                    .map(|p| (p - dipole.center).magnitude())
+                    .fold(0.0, f32::max);
+                dipole
+            };
+        }
+
+        WindingNumbers {
+            tree,
+            vertices,
+            dipoles,
+        }
+    }
+
+    pub fn winding_number(&self, point: Vector3<f32>) -> f32 {
+        if self.tree.triangles.is_empty() {
+            return 0.0;
+        }
+
+        let mut sum = 0.0;
+        let mut stack = vec![0u32];
+
+        while let Some(node_index) = stack.pop() {
+            let dipole = &self.dipoles[node_index as usize];
+            let offset = dipole.center - point;
+            let distance = offset.magnitude();
+            if distance > FAR_FIELD_RATIO * dipole.radius {
+                sum += offset.dot(dipole.area_vector) / (distance * distance * distance);
+                continue;
+            }
+
+            let node = &self.tree.nodes[node_index as usize];
+            if node.is_branch() {
+                stack.push(node.left_or_offset);
+                stack.push(node.left_or_offset + 1);
+            } else {
+                for triangle_index in node.left_or_offset..node.left_or_offset + node.count {
+                    let angle = solid_angle(point, self.tree.triangle_vertices(self.vertices, triangle_index));
+                    if angle.is_finite() {
+                        sum += angle;
+                    }
+                }
+            }
+        }
+
+        sum / (4.0 * std::f32::consts::PI)
+    }
+
+    #[inline]
+    pub fn contains(&self, point: Vector3<f32>) -> bool {
+        self.winding_number(point) >= 0.5
+    }
+
+    /// Distance to the closest triangle, negative inside the mesh. Returns
+    /// infinity when the tree has no triangles.
+    pub fn signed_distance(&self, point: Vector3<f32>) -> f32 {
+        match self.tree.closest_point(self.vertices, point) {
+            Some(closest) => {
+                let distance = closest.distance2.sqrt();
+                if self.contains(point) {
+                    -distance
+                } else {
+                    distance
+                }
+            }
+            None => f32::INFINITY,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Neighbor {
+    /// Index into `Tree::triangles`.
+    pub triangle_index: u32,
+    pub point: Vector3<f32>,
+    pub distance2: f32,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct QueueItem {
+    distance2: f32,
+    node_index: u32,
+}
+
+impl PartialEq for QueueItem {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for QueueItem {}
+
+impl PartialOrd for QueueItem {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for QueueItem {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Reversed so the `BinaryHeap` pops the closest node first.
+        other.distance2.total_cmp(&self.distance2)
+    }
+}
+
+/// Scratch buffers for nearest neighbor queries. Keep one around and pass it
+/// to every query to avoid allocating per query.
+#[derive(Debug, Default)]
+pub struct NeighborSearch {
+    queue: BinaryHeap<QueueItem>,
+    neighbors: Vec<Neighbor>,
+}
+
+impl NeighborSearch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<'a> TreeView<'a> {
+    /// Visits triangles in the order of the distance of their containing
+    /// nodes to `point`. Nodes farther away than the squared distance bound
+    /// are pruned. The bound starts at `max_distance2` and is replaced by the
+    /// value `visit` returns.
+    fn visit_nearest<F>(
+        &self,
+        vertices: &[Vector3<f32>],
+        point: Vector3<f32>,
+        queue: &mut BinaryHeap<QueueItem>,
+        mut max_distance2: f32,
+        mut visit: F,
+    ) where
+        F: FnMut(Neighbor) -> f32,
+    {
+        queue.clear();
+        if self.triangles.is_empty() {
+            return;
+        }
+
+        queue.push(QueueItem {
+            distance2: self.nodes[0].aabb().distance2_to_point(point),
+            node_index: 0,
+        });
+
+        while let Some(QueueItem { distance2, node_index }) = queue.pop() {
+            if distance2 > max_distance2 {
+                break;
+            }
+
+            let node = &self.nodes[node_index as usize];
+            if node.is_branch() {
+                for child_index in node.left_or_offset..node.left_or_offset + 2 {
+                    let child = &self.nodes[child_index as usize];
+                    queue.push(QueueItem {
+                        distance2: child.aabb().distance2_to_point(point),
+                        node_index: child_index,
+                    });
+                }
+            } else {
+                for triangle_index in node.left_or_offset..node.left_or_offset + node.count {
+                    let triangle = self.triangle_vertices(vertices, triangle_index);
+                    let closest = closest_point_on_triangle(point, triangle);
+                    max_distance2 = visit(Neighbor {
+                        triangle_index,
+                        point: closest,
+                        distance2: (closest - point).magnitude2(),
+                    });
+                }
+            }
+        }
+
+        queue.clear();
+    }
+
+    /// Returns up to `k` triangles closest to `point` in ascending order of
+    /// distance.
+    pub fn k_nearest_triangles<'s>(
+        &self,
+        vertices: &[Vector3<f32>],
+        point: Vector3<f32>,
+        k: usize,
+        search: &'s mut NeighborSearch,
+    ) -> &'s [Neighbor] {
+        let NeighborSearch { queue, neighbors } = search;
+        neighbors.clear();
+
+        if k == 0 {
+            return neighbors;
+        }
+
+        self.visit_nearest(vertices, point, queue, f32::INFINITY, |neighbor| {
+            let index = neighbors
+                .iter()
+                .position(|n| n.distance2 > neighbor.distance2)
+                .unwrap_or(neighbors.len());
+            if index < k {
+                neighbors.insert(index, neighbor);
+                neighbors.truncate(k);
+            }
+            if neighbors.len() < k {
+                f32::INFINITY
+            } else {
+                neighbors[k - 1].distance2
+            }
+        });
+
+        neighbors
+    }
+
+    /// Returns every triangle within `radius` of `point` in ascending order of
+    /// distance. A negative or NaN radius returns no triangles.
+    pub fn triangles_within_radius<'s>(
+        &self,
+        vertices: &[Vector3<f32>],
+        point: Vector3<f32>,
+        radius: f32,
+        search: &'s mut NeighborSearch,
+    ) -> &'s [Neighbor] {
+        let NeighborSearch { queue, neighbors } = search;
+        neighbors.clear();
+        if radius.is_nan() || radius < 0.0 {
+            return neighbors;
+        }
+
+        let radius2 = radius * radius;
+        self.visit_nearest(vertices, point, queue, radius2, |neighbor| {
+            if neighbor.distance2 <= radius2 {
+                neighbors.push(neighbor);
+            }
+            radius2
+        });
+
+        neighbors.sort_by(|a, b| a.distance2.total_cmp(&b.distance2));
+        neighbors
+    }
+}
+
+impl Tree {
+    #[inline]
+    pub fn closest_point(&self, vertices: &[Vector3<f32>], point: Vector3<f32>) -> Option<ClosestPoint> {
+        self.view().closest_point(vertices, point)
+    }
+
+    #[inline]
+    pub fn winding_numbers<'a>(&'a self, vertices: &'a [Vector3<f32>]) -> WindingNumbers<'a> {
+        WindingNumbers::new(self.view(), vertices)
+    }
+
+    /// Builds the winding numbers for a single query, which takes time
+    /// linear in the number of triangles. Use `winding_numbers` to answer
+    /// many queries.
+    #[inline]
+    pub fn contains(&self, vertices: &[Vector3<f32>], point: Vector3<f32>) -> bool {
+        self.winding_numbers(vertices).contains(point)
+    }
+
+    /// Like `contains`, builds the winding numbers for a single query.
+    #[inline]
+    pub fn signed_distance(&self, vertices: &[Vector3<f32>], point: Vector3<f32>) -> f32 {
+        self.winding_numbers(vertices).signed_distance(point)
+    }
+
+    #[inline]
+    pub fn k_nearest_triangles<'s>(
+        &self,
+        vertices: &[Vector3<f32>],
+        point: Vector3<f32>,
+        k: usize,
+        search: &'s mut NeighborSearch,
+    ) -> &'s [Neighbor] {
+        self.view().k_nearest_triangles(vertices, point, k, search)
+    }
+
+    #[inline]
+    pub fn triangles_within_radius<'s>(
+        &self,
+        vertices: &[Vector3<f32>],
+        point: Vector3<f32>,
+        radius: f32,
+        search: &'s mut NeighborSearch,
+    ) -> &'s [Neighbor] {
+        self.view().triangles_within_radius(vertices, point, radius, search)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    /// Unit cube with every face split into `n` by `n` quads, outward facing.
+    fn subdivided_cube(n: u32) -> (Vec<Vector3<f32>>, Vec<Triangle>) {
+        let mut vertices = Vec::new();
+        let mut triangles = Vec::new();
+        for axis in 0..3 {
+            for &side in &[0.0, 1.0] {
+                let base = vertices.len() as u32;
+                for j in 0..=n {
+                    for i in 0..=n {
+                        let mut p = [0.0; 3];
+                        p[axis] = side;
+                        p[(axis + 1) % 3] = i as f32 / n as f32;
+                        p[(axis + 2) % 3] = j as f32 / n as f32;
+                        vertices.push(v(p[0], p[1], p[2]));
+                    }
+                }
+                for j in 0..n {
+                    for i in 0..n {
+                        let a = base + j * (n + 1) + i;
+                        let (b, c, d) = (a + 1, a + n + 2, a + n + 1);
+                        if side > 0.0 {
+                            triangles.push([a, b, c]);
+                            triangles.push([a, c, d]);
+                        } else {
+                            triangles.push([a, c, b]);
+                            triangles.push([a, d, c]);
+                        }
+                    }
+                }
+            }
+        }
+        (vertices, triangles)
+    }
+
+    fn brute_force_winding_number(vertices: &[Vector3<f32>], triangles: &[Triangle], point: Vector3<f32>) -> f32 {
+        let sum: f32 = triangles
+            .iter()
+            .map(|t| solid_angle(point, [vertices[t[0] as usize], vertices[t[1] as usize], vertices[t[2] as usize]]))
+            .sum();
+        sum / (4.0 * std::f32::consts::PI)
+    }
+
+    fn brute_force_closest_distance2(vertices: &[Vector3<f32>], triangles: &[Triangle], point: Vector3<f32>) -> f32 {
+        triangles
+            .iter()
+            .map(|t| {
+                let triangle = [vertices[t[0] as usize], vertices[t[1] as usize], vertices[t[2] as usize]];
+                (closest_point_on_triangle(point, triangle) - point).magnitude2()
+            })
+            .fold(f32::INFINITY, f32::min)
+    }
+
+    #[test]
+    fn signed_distance_of_cube() {
+        let (vertices, triangles) = unit_cube();
+        let tree = Tree::new(&vertices, &triangles);
+        let winding_numbers = tree.winding_numbers(&vertices);
+
+        assert!((winding_numbers.winding_number(v(0.5, 0.5, 0.5)) - 1.0).abs() < 1e-4);
+        assert!(winding_numbers.winding_number(v(3.0, 0.5, 0.5)).abs() < 1e-4);
+
+        assert!((winding_numbers.signed_distance(v(0.5, 0.5, 0.5)) + 0.5).abs() < 1e-6);
+        assert!((winding_numbers.signed_distance(v(0.5, 0.5, 2.0)) - 1.0).abs() < 1e-6);
+        assert!((winding_numbers.signed_distance(v(2.0, 2.0, 0.5)) - 2f32.sqrt()).abs() < 1e-6);
+
+        assert!(tree.contains(&vertices, v(0.5, 0.5, 0.5)));
+        assert!(!tree.contains(&vertices, v(3.0, 0.5, 0.5)));
+        assert!((tree.signed_distance(&vertices, v(0.5, 0.5, 2.0)) - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn multi_leaf_queries_match_brute_force() {
+        let (vertices, triangles) = subdivided_cube(6);
+        let tree = Tree::new(&vertices, &triangles);
+        assert!(tree.stats().leaf_count > 8);
+        let winding_numbers = tree.winding_numbers(&vertices);
+
+        // A lattice of points inside, outside and close to the surface.
+        for k in 0..9 {
+            for j in 0..9 {
+                for i in 0..9 {
+                    let point = v(i as f32 * 0.3 - 0.7, j as f32 * 0.3 - 0.65, k as f32 * 0.3 - 0.55);
+
+                    let expected = brute_force_winding_number(&vertices, &triangles, point);
+                    let actual = winding_numbers.winding_number(point);
+                    assert!((expected - actual).abs() < 0.05, "{:?}: {} vs {}", point, expected, actual);
+
+                    let inside = (0.0..=1.0).contains(&point.x)
+                        && (0.0..=1.0).contains(&point.y)
+                        && (0.0..=1.0).contains(&point.z);
+                    assert_eq!(inside, winding_numbers.contains(point), "{:?}", point);
+
+                    let expected = brute_force_closest_distance2(&vertices, &triangles, point);
+                    let closest = tree.closest_point(&vertices, point).unwrap();
+                    assert!((expected - closest.distance2).abs() < 1e-5, "{:?}", point);
+                    assert!(((closest.point - point).magnitude2() - closest.distance2).abs() < 1e-5);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn nearest_triangles_of_cube() {
+        let (vertices, triangles) = unit_cube();
+        let tree = Tree::new(&vertices, &triangles);
+        let mut search = NeighborSearch::new();
+
+        // Both triangles of the top face are at distance 1, the side faces
+        // are farther away.
+        let point = v(0.5, 0.5, 2.0);
+        let nearest = tree.k_nearest_triangles(&vertices, point, 3, &mut search);
+        assert_eq!(3, nearest.len());
+        assert!((nearest[0].distance2 - 1.0).abs() < 1e-6);
+        assert!((nearest[1].distance2 - 1.0).abs() < 1e-6);
+        assert!(nearest[2].distance2 > 1.0);
+
+        let within = tree.triangles_within_radius(&vertices, point, 1.1, &mut search);
+        assert_eq!(2, within.len());
+
+        let within = tree.triangles_within_radius(&vertices, point, 10.0, &mut search);
+        assert_eq!(12, within.len());
+        assert!(within.windows(2).all(|w| w[0].distance2 <= w[1].distance2));
+
+        // A negative radius does not wrap around to a positive one.
+        assert!(tree.triangles_within_radius(&vertices, point, -10.0, &mut search).is_empty());
+        assert!(tree.triangles_within_radius(&vertices, point, f32::NAN, &mut search).is_empty());
+    }
+
+    #[test]
+    fn nearest_triangles_match_brute_force() {
+        let (vertices, triangles) = subdivided_cube(4);
+        let tree = Tree::new(&vertices, &triangles);
+        assert!(tree.stats().leaf_count > 4);
+        let mut search = NeighborSearch::new();
+
+        let distance2 = |triangle_index: u32, point: Vector3<f32>| {
+            let triangle = tree.triangle_vertices(&vertices, triangle_index);
+            (closest_point_on_triangle(point, triangle) - point).magnitude2()
+        };
+
+        for &point in &[v(0.3, 0.6, 1.4), v(-0.5, 0.2, 0.7), v(0.45, 0.55, 0.4), v(2.0, -1.0, 3.0)] {
+            let mut expected: Vec<f32> = (0..tree.triangles.len() as u32).map(|t| distance2(t, point)).collect();
+            expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+            let nearest = tree.k_nearest_triangles(&vertices, point, 10, &mut search);
+            assert_eq!(10, nearest.len());
+            for (neighbor, &expected) in nearest.iter().zip(expected.iter()) {
+                assert!((neighbor.distance2 - expected).abs() < 1e-5, "{:?}", point);
+                assert!((neighbor.distance2 - distance2(neighbor.triangle_index, point)).abs() < 1e-5);
+            }
+
+            let radius = expected[20].sqrt() + 1e-3;
+            let within = tree.triangles_within_radius(&vertices, point, radius, &mut search);
+            let expected_count = expected.iter().filter(|&&d2| d2 <= radius * radius).count();
+            assert_eq!(expected_count, within.len(), "{:?}", point);
+            assert!(within.windows(2).all(|w| w[0].distance2 <= w[1].distance2));
+            for neighbor in within {
+                assert!((neighbor.distance2 - distance2(neighbor.triangle_index, point)).abs() < 1e-5);
+            }
+        }
+    }
+}
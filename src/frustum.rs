@@ -1,6 +1,10 @@
+use cgmath::{EuclideanSpace, InnerSpace, Point2, Point3, Transform, Vector2};
 use num_traits::{cast, Float};
+use crate::aabb::*;
+use crate::cull::*;
 use crate::matrix::*;
 use crate::range::*;
+use crate::ray::*;
 
 /// linear interpolation coefficients
 fn lic<T: Float>(x0: T, x1: T, y0: T, y1: T) -> (T, T) {
@@ -8,6 +12,25 @@ fn lic<T: Float>(x0: T, x1: T, y0: T, y1: T) -> (T, T) {
     ((y1 - y0) * d, (x1 * y0 - x0 * y1) * d)
 }
 
+#[inline]
+fn lerp<T: Float>(a: T, b: T, t: T) -> T {
+    (T::one() - t) * a + t * b
+}
+
+/// Converts window pixel coordinates, with the origin at the top left corner
+/// and y pointing down, to normalized coordinates in [0, 1] with the origin at
+/// the bottom left corner and y pointing up.
+#[inline]
+pub fn pixel_to_normalized<T: Float>(pixel: Point2<T>, dimensions: Vector2<T>) -> Point2<T> {
+    Point2::new(pixel.x / dimensions.x, T::one() - pixel.y / dimensions.y)
+}
+
+/// Inverse of `pixel_to_normalized`.
+#[inline]
+pub fn normalized_to_pixel<T: Float>(normalized: Point2<T>, dimensions: Vector2<T>) -> Point2<T> {
+    Point2::new(normalized.x * dimensions.x, (T::one() - normalized.y) * dimensions.y)
+}
+
 #[derive(Debug, Copy, Clone)]
 pub struct ClassicFrustum3<T> {
     pub l: T,
@@ -18,6 +41,152 @@ pub struct ClassicFrustum3<T> {
     pub f: T,
 }
 
+impl<T: Float> ClassicFrustum3<T> {
+    /// Inverse of `Frustum3::from_classic`.
+    #[inline]
+    pub fn from_frustum(frustum: &Frustum3<T>) -> Self {
+        let n = -frustum.z1;
+        Self {
+            l: frustum.x0 * n,
+            r: frustum.x1 * n,
+            b: frustum.y0 * n,
+            t: frustum.y1 * n,
+            n,
+            f: -frustum.z0,
+        }
+    }
+}
+
+impl<T: cgmath::BaseFloat> ClassicFrustum3<T> {
+    /// Recovers the frustum from a perspective projection matrix, see
+    /// `Frustum3::from_matrix`.
+    pub fn from_matrix(matrix: &Matrix4<T>, range: &Range3<T>) -> Result<Self, FromMatrixError> {
+        match Frustum3::from_matrix(matrix, range)? {
+            (frustum, Projection::Perspective) => Ok(Self::from_frustum(&frustum)),
+            (_, Projection::Orthographic) => Err(FromMatrixError::NotPerspective),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Projection {
+    Perspective,
+    Orthographic,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum FromMatrixError {
+    /// The matrix is neither a perspective nor an orthographic projection.
+    NotAProjection,
+    /// The matrix is an orthographic projection where a perspective
+    /// projection is required.
+    NotPerspective,
+    /// The matrix is a projection onto an empty or inverted view volume.
+    Degenerate,
+}
+
+impl std::fmt::Display for FromMatrixError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            FromMatrixError::NotAProjection => write!(f, "matrix is not a projection"),
+            FromMatrixError::NotPerspective => write!(f, "matrix is not a perspective projection"),
+            FromMatrixError::Degenerate => write!(f, "matrix projects an empty view volume"),
+        }
+    }
+}
+
+impl std::error::Error for FromMatrixError {}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Sphere3<T> {
+    pub center: Point3<T>,
+    pub radius: T,
+}
+
+/// The corners of a view volume. The first four lie on the near plane and the
+/// last four on the far plane, both ordered (x0, y0), (x1, y0), (x0, y1), (x1, y1).
+#[derive(Debug, Copy, Clone)]
+pub struct FrustumCorners<T> {
+    pub points: [Point3<T>; 8],
+}
+
+impl<T: cgmath::BaseFloat> FrustumCorners<T> {
+    /// A sphere centered on the average of the corners that contains all of
+    /// them. Not minimal, but it only depends on the shape of the frustum and
+    /// not on its orientation, which keeps shadow map texels from swimming
+    /// when the camera rotates. Returns `None` when a corner is not finite,
+    /// as with an infinite far plane.
+    pub fn bounding_sphere(&self) -> Option<Sphere3<T>> {
+        if !self.points.iter().all(|p| p.x.is_finite() && p.y.is_finite() && p.z.is_finite()) {
+            return None;
+        }
+        let sum = self
+            .points
+            .iter()
+            .fold(cgmath::Vector3::new(T::zero(), T::zero(), T::zero()), |sum, p| sum + p.to_vec());
+        let center = Point3::from_vec(sum / cast(8).unwrap());
+        let radius = self
+            .points
+            .iter()
+            .map(|&p| (p - center).magnitude())
+            .fold(T::zero(), T::max);
+        Some(Sphere3 { center, radius })
+    }
+
+    pub fn aabb(&self) -> AABB3 {
+        AABB3::from_points(self.points.iter().map(|p| {
+            let p: Point3<f32> = p.cast().unwrap();
+            crate::vector::Vector3::from([p.x, p.y, p.z])
+        }))
+        .unwrap()
+    }
+}
+
+/// How to distribute split planes between the near and far plane.
+#[derive(Debug, Copy, Clone)]
+pub enum SplitScheme<T> {
+    /// Equal depth ranges.
+    Uniform,
+    /// Depth ranges with equal far to near ratios.
+    Logarithmic,
+    /// Blend between logarithmic (1) and uniform (0) splits (Zhang et al.
+    /// 2006). Values around 0.5 to 0.9 work well for cascaded shadow maps.
+    Practical(T),
+}
+
+/// Returns `count + 1` distances from `near` to `far`, where sub-range `i`
+/// spans from distance `i` to distance `i + 1`. Returns `None` when the
+/// scheme has a logarithmic part and `near` is not positive, as is common
+/// for orthographic projections.
+pub fn split_distances<T: Float>(near: T, far: T, count: usize, scheme: SplitScheme<T>) -> Option<Vec<T>> {
+    let lambda = match scheme {
+        SplitScheme::Uniform => T::zero(),
+        SplitScheme::Logarithmic => T::one(),
+        SplitScheme::Practical(lambda) => lambda,
+    };
+    if lambda != T::zero() && near <= T::zero() {
+        return None;
+    }
+    let n: T = cast(count).unwrap();
+    let distances = (0..=count)
+        .map(|i| {
+            if i == count {
+                // Avoid rounding errors on the last split.
+                return far;
+            }
+            let f: T = cast::<usize, T>(i).unwrap() / n;
+            let uniform = near + (far - near) * f;
+            if lambda == T::zero() {
+                // Skip the logarithmic part, which is NaN for `near <= 0`.
+                return uniform;
+            }
+            let logarithmic = near * (far / near).powf(f);
+            lambda * logarithmic + (T::one() - lambda) * uniform
+        })
+        .collect();
+    Some(distances)
+}
+
 #[derive(Debug, Copy, Clone)]
 pub struct Frustum3<T> {
     pub x0: T,
@@ -130,6 +299,58 @@ impl<T: Float> Frustum3<T> {
         )
     }
 
+    /// Perspective projection with the far plane at infinity. Ignores `z0`
+    /// and maps points infinitely far away to `range.z0`.
+    #[inline]
+    pub fn infinite_perspective(&self, range: &Range3<T>) -> Matrix4<T> {
+        let zero = T::zero();
+        let one = T::one();
+        let (ax, bx) = lic(self.x0, self.x1, range.x0, range.x1);
+        let (ay, by) = lic(self.y0, self.y1, range.y0, range.y1);
+        let (az, bz) = (-range.dz() * self.z1, range.z0);
+
+        Matrix4::new(
+            ax, zero, zero, zero, // c0
+            zero, ay, zero, zero, // c1
+            -bx, -by, -bz, -one, // c2
+            zero, zero, az, zero, // c3
+        )
+    }
+
+    #[inline]
+    pub fn inverse_infinite_perspective(&self, range: &Range3<T>) -> Matrix4<T> {
+        let zero = T::zero();
+        let one = T::one();
+        let (ax, bx) = lic(range.x0, range.x1, self.x0, self.x1);
+        let (ay, by) = lic(range.y0, range.y1, self.y0, self.y1);
+        let (az, bz) = {
+            let d = one / (range.dz() * self.z1);
+            (-d, range.z0 * d)
+        };
+
+        Matrix4::new(
+            ax, zero, zero, zero, // c0
+            zero, ay, zero, zero, // c1
+            zero, zero, zero, az, // c2
+            bx, by, -one, bz, // c3
+        )
+    }
+
+    /// Infinite perspective projection for a [0, 1] clip space depth range
+    /// (`glClipControl(GL_LOWER_LEFT, GL_ZERO_TO_ONE)`) with the near plane at
+    /// depth 1 and infinity at depth 0. Floating point depth buffers have most
+    /// precision near 0, which this spends on distant geometry where the
+    /// projection has the least.
+    #[inline]
+    pub fn reversed_z_infinite_perspective(&self) -> Matrix4<T> {
+        self.infinite_perspective(&Range3::reversed_z())
+    }
+
+    #[inline]
+    pub fn inverse_reversed_z_infinite_perspective(&self) -> Matrix4<T> {
+        self.inverse_infinite_perspective(&Range3::reversed_z())
+    }
+
     #[inline]
     pub fn dx(&self) -> T {
         self.x1 - self.x0
@@ -160,3 +381,564 @@ impl<T: Float> Frustum3<T> {
         })
     }
 }
+
+impl<T: cgmath::BaseFloat> Frustum3<T> {
+    /// World space corners of the perspective view volume. An infinite far
+    /// plane results in infinite far corners.
+    pub fn perspective_corners(&self, pos_to_parent: &Matrix4<T>) -> FrustumCorners<T> {
+        let corner =
+            |x: T, y: T, z: T| pos_to_parent.transform_point(Point3::new(-x * z, -y * z, z));
+        FrustumCorners {
+            points: [
+                corner(self.x0, self.y0, self.z1),
+                corner(self.x1, self.y0, self.z1),
+                corner(self.x0, self.y1, self.z1),
+                corner(self.x1, self.y1, self.z1),
+                corner(self.x0, self.y0, self.z0),
+                corner(self.x1, self.y0, self.z0),
+                corner(self.x0, self.y1, self.z0),
+                corner(self.x1, self.y1, self.z0),
+            ],
+        }
+    }
+
+    /// World space corners of the orthographic view volume.
+    pub fn orthographic_corners(&self, pos_to_parent: &Matrix4<T>) -> FrustumCorners<T> {
+        let corner = |x: T, y: T, z: T| pos_to_parent.transform_point(Point3::new(x, y, z));
+        FrustumCorners {
+            points: [
+                corner(self.x0, self.y0, self.z1),
+                corner(self.x1, self.y0, self.z1),
+                corner(self.x0, self.y1, self.z1),
+                corner(self.x1, self.y1, self.z1),
+                corner(self.x0, self.y0, self.z0),
+                corner(self.x1, self.y0, self.z0),
+                corner(self.x0, self.y1, self.z0),
+                corner(self.x1, self.y1, self.z0),
+            ],
+        }
+    }
+
+    /// Divides the depth range into `count` consecutive sub-frusta, ordered
+    /// from near to far. Works for both perspective and orthographic
+    /// frusta since only the depth range changes. Returns `None` when the
+    /// far plane is at infinity, as recovered from an infinite perspective
+    /// projection; set `z0` to the farthest distance of interest first.
+    /// Also returns `None` when the near plane is at or behind the origin
+    /// and the scheme has a logarithmic part, see `split_distances`.
+    pub fn split(&self, count: usize, scheme: SplitScheme<T>) -> Option<Vec<Self>> {
+        if !self.z0.is_finite() || !self.z1.is_finite() {
+            return None;
+        }
+        let distances = split_distances(-self.z1, -self.z0, count, scheme)?;
+        Some(
+            distances
+                .windows(2)
+                .map(|d| Frustum3 {
+                    z0: -d[1],
+                    z1: -d[0],
+                    ..*self
+                })
+                .collect(),
+        )
+    }
+
+    /// Recovers the frustum from a matrix that maps it onto the clip space
+    /// `range`, as produced by `perspective`, `infinite_perspective` or
+    /// `orthographic`. Matrices that differ from those by a non-zero scale
+    /// factor describe the same projection and are accepted too. An
+    /// infinite far plane results in a `z0` of negative infinity.
+    pub fn from_matrix(
+        matrix: &Matrix4<T>,
+        range: &Range3<T>,
+    ) -> Result<(Self, Projection), FromMatrixError> {
+        let max_abs = (0..4)
+            .flat_map(|c| (0..4).map(move |r| (c, r)))
+            .map(|(c, r)| matrix[c][r].abs())
+            .fold(T::zero(), T::max);
+        let tolerance = max_abs * cast(1e-6).unwrap();
+        let is_zero = |value: T| value.abs() <= tolerance;
+        let all_zero =
+            |entries: &[(usize, usize)]| entries.iter().all(|&(c, r)| is_zero(matrix[c][r]));
+
+        let (frustum, projection) = if !is_zero(matrix[2][3]) && is_zero(matrix[3][3]) {
+            if !all_zero(&[(0, 1), (0, 2), (0, 3), (1, 0), (1, 2), (1, 3), (3, 0), (3, 1)]) {
+                return Err(FromMatrixError::NotAProjection);
+            }
+            // Normalize so the w row is (0, 0, -1, 0).
+            let m = matrix * (-T::one() / matrix[2][3]);
+            let (ax, bx) = (m[0][0], -m[2][0]);
+            let (ay, by) = (m[1][1], -m[2][1]);
+            let (az, bz) = (m[3][2], -m[2][2]);
+            if is_zero(ax) || is_zero(ay) || is_zero(az) {
+                return Err(FromMatrixError::Degenerate);
+            }
+            // Clip space depth is bz - az / z.
+            let z0 = if is_zero(bz - range.z0) {
+                T::neg_infinity()
+            } else {
+                az / (bz - range.z0)
+            };
+            let z1 = az / (bz - range.z1);
+            if !(z0 < z1 && z1 < T::zero()) {
+                return Err(FromMatrixError::Degenerate);
+            }
+            let frustum = Frustum3 {
+                x0: (range.x0 - bx) / ax,
+                x1: (range.x1 - bx) / ax,
+                y0: (range.y0 - by) / ay,
+                y1: (range.y1 - by) / ay,
+                z0,
+                z1,
+            };
+            (frustum, Projection::Perspective)
+        } else if is_zero(matrix[2][3]) && !is_zero(matrix[3][3]) {
+            if !all_zero(&[(0, 1), (0, 2), (0, 3), (1, 0), (1, 2), (1, 3), (2, 0), (2, 1)]) {
+                return Err(FromMatrixError::NotAProjection);
+            }
+            // Normalize so the w row is (0, 0, 0, 1).
+            let m = matrix * (T::one() / matrix[3][3]);
+            let (ax, bx) = (m[0][0], m[3][0]);
+            let (ay, by) = (m[1][1], m[3][1]);
+            let (az, bz) = (m[2][2], m[3][2]);
+            if is_zero(ax) || is_zero(ay) || is_zero(az) {
+                return Err(FromMatrixError::Degenerate);
+            }
+            let frustum = Frustum3 {
+                x0: (range.x0 - bx) / ax,
+                x1: (range.x1 - bx) / ax,
+                y0: (range.y0 - by) / ay,
+                y1: (range.y1 - by) / ay,
+                z0: (range.z0 - bz) / az,
+                z1: (range.z1 - bz) / az,
+            };
+            if frustum.z0.partial_cmp(&frustum.z1) != Some(std::cmp::Ordering::Less) {
+                return Err(FromMatrixError::Degenerate);
+            }
+            (frustum, Projection::Orthographic)
+        } else {
+            return Err(FromMatrixError::NotAProjection);
+        };
+
+        if !(frustum.x0 < frustum.x1 && frustum.y0 < frustum.y1) {
+            return Err(FromMatrixError::Degenerate);
+        }
+
+        Ok((frustum, projection))
+    }
+
+    /// World space ray through the point at normalized coordinates `p` (see
+    /// `pixel_to_normalized`), starting at the camera position.
+    pub fn perspective_ray(&self, pos_to_parent: &Matrix4<T>, p: Point2<T>) -> Ray<T> {
+        let direction = cgmath::Vector3::new(
+            lerp(self.x0, self.x1, p.x),
+            lerp(self.y0, self.y1, p.y),
+            -T::one(),
+        );
+        Ray {
+            origin: pos_to_parent.transform_point(Point3::origin()),
+            direction: pos_to_parent.transform_vector(direction).normalize(),
+        }
+    }
+
+    /// World space ray through the point at normalized coordinates `p` (see
+    /// `pixel_to_normalized`), starting on the near plane.
+    pub fn orthographic_ray(&self, pos_to_parent: &Matrix4<T>, p: Point2<T>) -> Ray<T> {
+        let origin = Point3::new(
+            lerp(self.x0, self.x1, p.x),
+            lerp(self.y0, self.y1, p.y),
+            self.z1,
+        );
+        let direction = cgmath::Vector3::new(T::zero(), T::zero(), -T::one());
+        Ray {
+            origin: pos_to_parent.transform_point(origin),
+            direction: pos_to_parent.transform_vector(direction).normalize(),
+        }
+    }
+
+    /// Normalized coordinates of the world space point, or `None` when the
+    /// point is not in front of the camera.
+    pub fn perspective_project(
+        &self,
+        pos_from_parent: &Matrix4<T>,
+        point: Point3<T>,
+    ) -> Option<Point2<T>> {
+        let p = pos_from_parent.transform_point(point);
+        if p.z >= T::zero() {
+            return None;
+        }
+        let x = p.x / -p.z;
+        let y = p.y / -p.z;
+        Some(Point2::new((x - self.x0) / self.dx(), (y - self.y0) / self.dy()))
+    }
+
+    /// Normalized coordinates of the world space point.
+    pub fn orthographic_project(
+        &self,
+        pos_from_parent: &Matrix4<T>,
+        point: Point3<T>,
+    ) -> Point2<T> {
+        let p = pos_from_parent.transform_point(point);
+        Point2::new((p.x - self.x0) / self.dx(), (p.y - self.y0) / self.dy())
+    }
+
+    /// Planes bounding the perspective view volume, expressed in the parent
+    /// space of the camera given its `pos_from_parent` matrix.
+    pub fn perspective_planes(&self, pos_from_parent: &Matrix4<T>) -> FrustumPlanes<T> {
+        let zero = T::zero();
+        let one = T::one();
+        let plane = |x, y, z, d| Plane3::new(cgmath::Vector3::new(x, y, z), d);
+        FrustumPlanes {
+            planes: [
+                plane(one, zero, self.x0, zero),
+                plane(-one, zero, -self.x1, zero),
+                plane(zero, one, self.y0, zero),
+                plane(zero, -one, -self.y1, zero),
+                plane(zero, zero, one, -self.z0),
+                plane(zero, zero, -one, self.z1),
+            ],
+        }
+        .transform(pos_from_parent)
+    }
+
+    /// Planes bounding the orthographic view volume, expressed in the parent
+    /// space of the camera given its `pos_from_parent` matrix.
+    pub fn orthographic_planes(&self, pos_from_parent: &Matrix4<T>) -> FrustumPlanes<T> {
+        let zero = T::zero();
+        let one = T::one();
+        let plane = |x, y, z, d| Plane3::new(cgmath::Vector3::new(x, y, z), d);
+        FrustumPlanes {
+            planes: [
+                plane(one, zero, zero, -self.x0),
+                plane(-one, zero, zero, self.x1),
+                plane(zero, one, zero, -self.y0),
+                plane(zero, -one, zero, self.y1),
+                plane(zero, zero, one, -self.z0),
+                plane(zero, zero, -one, self.z1),
+            ],
+        }
+        .transform(pos_from_parent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::*;
+
+    fn frustum() -> Frustum3<f64> {
+        Frustum3 {
+            x0: -1.2,
+            x1: 0.8,
+            y0: -0.6,
+            y1: 0.9,
+            z0: -50.0,
+            z1: -0.5,
+        }
+    }
+
+    fn range() -> Range3<f64> {
+        Range3 {
+            x0: -1.0,
+            x1: 1.0,
+            y0: -1.0,
+            y1: 1.0,
+            z0: 1.0,
+            z1: -1.0,
+        }
+    }
+
+    fn pos_to_parent() -> Matrix4<f64> {
+        Matrix4::from_translation(Vector3::new(1.0, 2.0, 3.0))
+            * Matrix4::from_angle_y(Deg(30.0))
+            * Matrix4::from_angle_x(Deg(-20.0))
+    }
+
+    fn normalized_to_ndc(p: Point2<f64>, range: &Range3<f64>) -> (f64, f64) {
+        (lerp(range.x0, range.x1, p.x), lerp(range.y0, range.y1, p.y))
+    }
+
+    /// Largest relative error in the view depth reconstructed from a 32-bit
+    /// floating point depth buffer storing `depth(ndc_z)`, sampled
+    /// logarithmically from the near to the far plane.
+    fn max_depth_error<D>(
+        frustum: &Frustum3<f64>,
+        forward: Matrix4<f64>,
+        inverse: Matrix4<f64>,
+        depth: D,
+    ) -> f64
+    where
+        D: Fn(f32) -> f32,
+    {
+        let forward = forward.cast::<f32>().unwrap();
+        let near = -frustum.z1;
+        let far = -frustum.z0;
+        let mut max_error = 0.0f64;
+        for i in 0..=1000 {
+            let z = near * (far / near).powf(i as f64 / 1000.0);
+            let clp = forward * Vector4::new(0.0, 0.0, -z as f32, 1.0);
+            let stored = depth(clp.z / clp.w);
+            let ndc_z = (stored as f64 - depth(0.0) as f64) / (depth(1.0) - depth(0.0)) as f64;
+            let cam = inverse * Vector4::new(0.0, 0.0, ndc_z, 1.0);
+            let reconstructed = -cam.z / cam.w;
+            max_error = max_error.max((reconstructed - z).abs() / z);
+        }
+        max_error
+    }
+
+    #[test]
+    fn reversed_z_improves_depth_precision() {
+        let frustum = Frustum3 {
+            x0: -1.0,
+            x1: 1.0,
+            y0: -1.0,
+            y1: 1.0,
+            z0: -10_000.0,
+            z1: -0.1,
+        };
+
+        // Classic OpenGL: [-1, 1] clip space depth remapped to [0, 1].
+        let classic = max_depth_error(
+            &frustum,
+            frustum.perspective(&range()),
+            frustum.inverse_perspective(&range()),
+            |ndc_z| ndc_z * 0.5 + 0.5,
+        );
+
+        let reversed = max_depth_error(
+            &frustum,
+            frustum.reversed_z_infinite_perspective(),
+            frustum.inverse_reversed_z_infinite_perspective(),
+            |ndc_z| ndc_z,
+        );
+
+        assert!(reversed < 1e-5, "reversed-z error {}", reversed);
+        assert!(reversed * 100.0 < classic, "reversed-z error {} classic {}", reversed, classic);
+    }
+
+    #[test]
+    fn infinite_perspective_round_trip() {
+        let frustum = frustum();
+        let range = range();
+        let m = frustum.infinite_perspective(&range) * frustum.inverse_infinite_perspective(&range);
+        assert!(m.abs_diff_eq(&Matrix4::identity(), 1e-12));
+
+        let cam_to_clp = frustum.infinite_perspective(&range);
+        let near = cam_to_clp * Vector4::new(0.0, 0.0, frustum.z1, 1.0);
+        assert_close(range.z1, near.z / near.w);
+        let far = cam_to_clp * Vector4::new(0.0, 0.0, -1e12, 1.0);
+        assert!((far.z / far.w - range.z0).abs() < 1e-9);
+    }
+
+    fn assert_frustum_close(a: &Frustum3<f64>, b: &Frustum3<f64>) {
+        assert_close(a.x0, b.x0);
+        assert_close(a.x1, b.x1);
+        assert_close(a.y0, b.y0);
+        assert_close(a.y1, b.y1);
+        assert!((a.z0 - b.z0).abs() < 1e-6 * b.z0.abs(), "{} != {}", a.z0, b.z0);
+        assert_close(a.z1, b.z1);
+    }
+
+    #[test]
+    fn frustum_from_matrix() {
+        let frustum = frustum();
+        let range = range();
+
+        let (f, p) = Frustum3::from_matrix(&frustum.perspective(&range), &range).unwrap();
+        assert_eq!(Projection::Perspective, p);
+        assert_frustum_close(&frustum, &f);
+
+        // Scaling the matrix does not change the projection.
+        let (f, p) = Frustum3::from_matrix(&(frustum.perspective(&range) * 3.0), &range).unwrap();
+        assert_eq!(Projection::Perspective, p);
+        assert_frustum_close(&frustum, &f);
+
+        let (f, p) = Frustum3::from_matrix(&frustum.orthographic(&range), &range).unwrap();
+        assert_eq!(Projection::Orthographic, p);
+        assert_frustum_close(&frustum, &f);
+
+        let reversed = Range3::reversed_z();
+        let m = frustum.infinite_perspective(&reversed);
+        let (f, p) = Frustum3::from_matrix(&m, &reversed).unwrap();
+        assert_eq!(Projection::Perspective, p);
+        assert_eq!(f64::NEG_INFINITY, f.z0);
+        assert_close(frustum.z1, f.z1);
+
+        let classic = ClassicFrustum3::from_matrix(&frustum.perspective(&range), &range).unwrap();
+        assert_frustum_close(&frustum, &Frustum3::from_classic(&classic));
+    }
+
+    #[test]
+    fn frustum_from_non_projection_matrix() {
+        let range = range();
+        assert_eq!(
+            Err(FromMatrixError::NotAProjection),
+            Frustum3::from_matrix(&Matrix4::from_angle_y(Deg(30.0)), &range).map(|_| ())
+        );
+        let m = frustum().perspective(&range) * pos_to_parent();
+        assert_eq!(
+            Err(FromMatrixError::NotAProjection),
+            Frustum3::from_matrix(&m, &range).map(|_| ())
+        );
+        let m = Matrix4::from_nonuniform_scale(1.0, 0.0, 1.0);
+        assert_eq!(
+            Err(FromMatrixError::Degenerate),
+            Frustum3::from_matrix(&m, &range).map(|_| ())
+        );
+        assert_eq!(
+            Err(FromMatrixError::NotPerspective),
+            ClassicFrustum3::from_matrix(&frustum().orthographic(&range), &range).map(|_| ())
+        );
+    }
+
+    #[test]
+    fn cascade_splits() {
+        let frustum = Frustum3 {
+            z0: -1000.0,
+            z1: -1.0,
+            ..frustum()
+        };
+        let pos_to_parent = pos_to_parent();
+
+        let cascades = frustum.split(3, SplitScheme::Logarithmic).unwrap();
+        assert_eq!(3, cascades.len());
+        assert_close(-1.0, cascades[0].z1);
+        assert_close(-10.0, cascades[0].z0);
+        assert_close(-100.0, cascades[1].z0);
+        assert_close(-1000.0, cascades[2].z0);
+
+        let uniform = split_distances(1.0, 1000.0, 3, SplitScheme::Uniform).unwrap();
+        let practical = split_distances(1.0, 1000.0, 3, SplitScheme::Practical(0.5)).unwrap();
+        assert_close(0.5 * (10.0 + uniform[1]), practical[1]);
+
+        // Consecutive cascades share their corners.
+        for pair in cascades.windows(2) {
+            let near = pair[0].perspective_corners(&pos_to_parent);
+            let far = pair[1].perspective_corners(&pos_to_parent);
+            for i in 0..4 {
+                assert!((near.points[i + 4] - far.points[i]).magnitude() < 1e-9);
+            }
+        }
+
+        let corners = frustum.perspective_corners(&pos_to_parent);
+        let sphere = corners.bounding_sphere().unwrap();
+        let aabb = corners.aabb();
+        for p in corners.points.iter() {
+            assert!((p - sphere.center).magnitude() <= sphere.radius + 1e-9);
+            let q = crate::vector::Vector3::from([p.x as f32, p.y as f32, p.z as f32]);
+            assert!(aabb.distance2_to_point(q) == 0.0);
+        }
+    }
+
+    #[test]
+    fn infinite_far_plane_cannot_be_bounded() {
+        let reversed = Range3::reversed_z();
+        let m = frustum().infinite_perspective(&reversed);
+        let (infinite, _) = Frustum3::from_matrix(&m, &reversed).unwrap();
+
+        assert!(infinite.split(3, SplitScheme::Logarithmic).is_none());
+        assert!(infinite.perspective_corners(&pos_to_parent()).bounding_sphere().is_none());
+
+        // Clamped to a finite distance both work again.
+        let clamped = Frustum3 { z0: -100.0, ..infinite };
+        let cascades = clamped.split(3, SplitScheme::Practical(0.5)).unwrap();
+        assert_close(-100.0, cascades[2].z0);
+        let sphere = clamped.perspective_corners(&pos_to_parent()).bounding_sphere().unwrap();
+        assert!(sphere.radius.is_finite());
+    }
+
+    #[test]
+    fn orthographic_splits() {
+        // Orthographic frusta usually start at the eye.
+        let frustum = Frustum3 {
+            z0: -90.0,
+            z1: 0.0,
+            ..frustum()
+        };
+        assert!(frustum.split(3, SplitScheme::Logarithmic).is_none());
+        assert!(frustum.split(3, SplitScheme::Practical(0.5)).is_none());
+        assert!(split_distances(-1.0, 90.0, 3, SplitScheme::Logarithmic).is_none());
+
+        let cascades = frustum.split(3, SplitScheme::Uniform).unwrap();
+        assert_close(0.0, cascades[0].z1);
+        assert_close(-30.0, cascades[0].z0);
+        assert_close(-60.0, cascades[1].z0);
+        assert_close(-90.0, cascades[2].z0);
+        let cascades = frustum.split(3, SplitScheme::Practical(0.0)).unwrap();
+        assert_close(-30.0, cascades[0].z0);
+    }
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn perspective_ray_round_trip() {
+        let frustum = frustum();
+        let range = range();
+        let pos_to_parent = pos_to_parent();
+        let pos_from_parent = pos_to_parent.invert().unwrap();
+        let clp_to_cam = frustum.inverse_perspective(&range);
+        let cam_to_clp = frustum.perspective(&range);
+
+        for &(x, y) in [(0.5, 0.5), (0.0, 0.0), (1.0, 1.0), (0.25, 0.8)].iter() {
+            let p = Point2::new(x, y);
+            let ray = frustum.perspective_ray(&pos_to_parent, p);
+
+            // The unprojected point on the near plane lies on the ray.
+            let (ndc_x, ndc_y) = normalized_to_ndc(p, &range);
+            let cam = clp_to_cam * Vector4::new(ndc_x, ndc_y, range.z1, 1.0);
+            let wld = pos_to_parent.transform_point(Point3::from_homogeneous(cam));
+            let t = (wld - ray.origin).magnitude();
+            assert!((ray.at(t) - wld).magnitude() < 1e-9);
+
+            // Projecting any point on the ray yields the original coordinates.
+            let q = frustum.perspective_project(&pos_from_parent, ray.at(7.0)).unwrap();
+            assert_close(x, q.x);
+            assert_close(y, q.y);
+
+            // Which agrees with the projection matrix.
+            let clp = cam_to_clp * pos_from_parent.transform_point(ray.at(7.0)).to_homogeneous();
+            assert_close(ndc_x, clp.x / clp.w);
+            assert_close(ndc_y, clp.y / clp.w);
+        }
+
+        let behind = pos_to_parent.transform_point(Point3::new(0.0, 0.0, 1.0));
+        assert!(frustum.perspective_project(&pos_from_parent, behind).is_none());
+    }
+
+    #[test]
+    fn orthographic_ray_round_trip() {
+        let frustum = frustum();
+        let range = range();
+        let pos_to_parent = pos_to_parent();
+        let pos_from_parent = pos_to_parent.invert().unwrap();
+        let clp_to_cam = frustum.inverse_orthographic(&range);
+
+        for &(x, y) in [(0.5, 0.5), (0.0, 0.0), (1.0, 1.0), (0.25, 0.8)].iter() {
+            let p = Point2::new(x, y);
+            let ray = frustum.orthographic_ray(&pos_to_parent, p);
+
+            let (ndc_x, ndc_y) = normalized_to_ndc(p, &range);
+            let cam = clp_to_cam * Vector4::new(ndc_x, ndc_y, range.z1, 1.0);
+            let wld = pos_to_parent.transform_point(Point3::from_homogeneous(cam));
+            assert!((ray.origin - wld).magnitude() < 1e-9);
+
+            let q = frustum.orthographic_project(&pos_from_parent, ray.at(7.0));
+            assert_close(x, q.x);
+            assert_close(y, q.y);
+        }
+    }
+
+    #[test]
+    fn pixel_round_trip() {
+        let dimensions = Vector2::new(1024.0, 768.0);
+        let pixel = Point2::new(100.0, 700.0);
+        let normalized = pixel_to_normalized(pixel, dimensions);
+        assert_close(100.0 / 1024.0, normalized.x);
+        assert_close(68.0 / 768.0, normalized.y);
+        let back = normalized_to_pixel(normalized, dimensions);
+        assert_close(pixel.x, back.x);
+        assert_close(pixel.y, back.y);
+    }
+}
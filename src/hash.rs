@@ -0,0 +1,70 @@
+/// The 64 bit Fowler–Noll–Vo 1a hash. Not cryptographic, but fast, simple
+/// and stable across platforms and releases, which makes it suitable for
+/// checksums and cache keys that end up in files.
+#[derive(Debug, Copy, Clone)]
+pub struct Fnv1a64 {
+    state: u64,
+}
+
+impl Fnv1a64 {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    #[inline]
+    pub fn new() -> Self {
+        Self {
+            state: Self::OFFSET_BASIS,
+        }
+    }
+
+    #[inline]
+    pub fn update(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.state ^= byte as u64;
+            self.state = self.state.wrapping_mul(Self::PRIME);
+        }
+    }
+
+    #[inline]
+    pub fn value(&self) -> u64 {
+        self.state
+    }
+}
+
+impl Default for Fnv1a64 {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::hash::Hasher for Fnv1a64 {
+    #[inline]
+    fn write(&mut self, bytes: &[u8]) {
+        self.update(bytes);
+    }
+
+    #[inline]
+    fn finish(&self) -> u64 {
+        self.value()
+    }
+}
+
+#[inline]
+pub fn fnv1a64(bytes: &[u8]) -> u64 {
+    let mut hasher = Fnv1a64::new();
+    hasher.update(bytes);
+    hasher.value()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_values() {
+        assert_eq!(0xcbf2_9ce4_8422_2325, fnv1a64(b""));
+        assert_eq!(0xaf63_dc4c_8601_ec8c, fnv1a64(b"a"));
+        assert_eq!(0x8594_4171_f739_67e8, fnv1a64(b"foobar"));
+    }
+}
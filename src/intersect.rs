@@ -1,6 +1,7 @@
 use cgmath::*;
 
 use crate::aabb::*;
+use crate::bvh::*;
 use crate::ray::*;
 
 pub fn ray_versus_aabb(ray: Ray, aabb: AABB3) -> Option<f32> {
@@ -57,6 +58,7 @@ pub fn ray_versus_aabb(ray: Ray, aabb: AABB3) -> Option<f32> {
     }
 }
 
+#[derive(Debug, Copy, Clone)]
 pub struct TriangleIntersection {
     pub t: f32,
     pub u: f32,
@@ -108,6 +110,82 @@ pub fn ray_versus_triangle(ray: Ray, triangle: [Point3<f32>; 3]) -> Option<Trian
     }
 }
 
+#[derive(Debug, Copy, Clone)]
+pub struct RayHit {
+    /// Index into `Tree::triangles`.
+    pub triangle_index: u32,
+    pub intersection: TriangleIntersection,
+}
+
+impl<'a> TreeView<'a> {
+    /// Finds the closest front facing triangle hit by the ray at a distance
+    /// below `max_t`.
+    pub fn ray_hit(
+        &self,
+        vertices: &[crate::vector::Vector3<f32>],
+        ray: Ray,
+        max_t: f32,
+    ) -> Option<RayHit> {
+        let origin = crate::vector::Vector3::from(Into::<[f32; 3]>::into(ray.origin));
+        let mut closest: Option<RayHit> = None;
+        let mut closest_t = max_t;
+        let mut stack = vec![0u32];
+
+        while let Some(node_index) = stack.pop() {
+            let node = &self.nodes[node_index as usize];
+            let aabb = node.aabb();
+
+            // A ray starting inside the box enters it immediately.
+            let entry_t = if aabb.distance2_to_point(origin) == 0.0 {
+                Some(0.0)
+            } else {
+                ray_versus_aabb(ray, aabb)
+            };
+            match entry_t {
+                Some(t) if t < closest_t => {}
+                _ => continue,
+            }
+
+            if node.is_branch() {
+                stack.push(node.left_or_offset);
+                stack.push(node.left_or_offset + 1);
+            } else {
+                for triangle_index in node.left_or_offset..node.left_or_offset + node.count {
+                    let [p0, p1, p2] = self.triangle_vertices(vertices, triangle_index);
+                    let triangle = [
+                        Point3::from(Into::<[f32; 3]>::into(p0)),
+                        Point3::from(Into::<[f32; 3]>::into(p1)),
+                        Point3::from(Into::<[f32; 3]>::into(p2)),
+                    ];
+                    if let Some(intersection) = ray_versus_triangle(ray, triangle) {
+                        if intersection.t < closest_t {
+                            closest_t = intersection.t;
+                            closest = Some(RayHit {
+                                triangle_index,
+                                intersection,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+
+        closest
+    }
+}
+
+impl Tree {
+    #[inline]
+    pub fn ray_hit(
+        &self,
+        vertices: &[crate::vector::Vector3<f32>],
+        ray: Ray,
+        max_t: f32,
+    ) -> Option<RayHit> {
+        self.view().ray_hit(vertices, ray, max_t)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -90,4 +90,6 @@ implement_with_keys!(
     (rshift, RShift),
     (lalt, LAlt),
     (ralt, RAlt),
+    (lcontrol, LControl),
+    (rcontrol, RControl),
 );
@@ -2,10 +2,26 @@ pub mod aabb;
 pub mod axis;
 pub mod bvh;
 pub mod camera;
+pub mod camera_bookmarks;
+pub mod camera_path;
 pub mod clamp;
+pub mod collide;
+pub mod cull;
+pub mod distance;
 pub mod frustum;
+pub mod grid;
+pub mod hash;
 pub mod intersect;
 pub mod matrix;
+pub mod ply;
+pub mod pod;
 pub mod range;
 pub mod ray;
+pub mod scene;
+pub mod stats;
+pub mod stl;
+#[cfg(test)]
+mod test_util;
+pub mod tree_file;
+pub mod validate;
 pub mod vector;
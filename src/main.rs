@@ -1,26 +1,14 @@
-mod as_bytes;
 mod keyboard_state;
 mod mouse_state;
 mod window_state;
 
-use as_bytes::*;
+use bvh::pod::*;
 
 use gl_typed as gl;
 use std::convert::TryInto;
 
-use bvh::vector::*;
-
 const CARGO_PKG_NAME: &'static str = env!("CARGO_PKG_NAME");
 
-pub type Triangle = [u32; 3];
-
-#[derive(Debug)]
-pub struct Scene {
-    pub pos_in_obj_buffer: Vec<[f32; 3]>,
-    pub triangle_buffer: Vec<[u32; 3]>,
-    pub mesh_descriptions: Vec<MeshDescription>,
-}
-
 const RGBA_PALETTE: [[f32; 4]; 32] = [
     [0.329, 0.588, 0.208, 1.000],
     [0.337, 0.475, 0.910, 1.000],
@@ -56,41 +44,6 @@ const RGBA_PALETTE: [[f32; 4]; 32] = [
     [0.835, 0.451, 0.286, 1.000],
 ];
 
-impl Scene {
-    pub fn from_meshes(meshes: &[Mesh]) -> Self {
-        let mut pos_in_obj_buffer = Vec::new();
-        let mut triangle_buffer = Vec::new();
-
-        let mesh_descriptions = meshes
-            .iter()
-            .map(|mesh| MeshDescription {
-                vertex_offset: {
-                    let offset = pos_in_obj_buffer.len().try_into().unwrap();
-                    pos_in_obj_buffer.extend(
-                        mesh.vertices
-                            .iter()
-                            .map(|&vertex| -> [f32; 3] { vertex.into() }),
-                    );
-                    offset
-                },
-                vertex_count: mesh.vertices.len().try_into().unwrap(),
-                triangle_offset: {
-                    let offset = triangle_buffer.len().try_into().unwrap();
-                    triangle_buffer.extend(&mesh.triangles[..]);
-                    offset
-                },
-                triangle_count: mesh.triangles.len().try_into().unwrap(),
-            })
-            .collect();
-
-        Scene {
-            pos_in_obj_buffer,
-            triangle_buffer,
-            mesh_descriptions,
-        }
-    }
-}
-
 const VS_POS_IN_OBJ_LOC: gl::AttributeLocation =
     unsafe { gl::AttributeLocation::from_i32_unchecked(0) };
 
@@ -102,82 +55,94 @@ const OBJ_TO_CLP_LOC: gl::UniformLocation = unsafe { gl::UniformLocation::from_i
 const CLP_TO_CAM_LOC: gl::UniformLocation = unsafe { gl::UniformLocation::from_i32_unchecked(1) };
 const RGBA_LOC: gl::UniformLocation = unsafe { gl::UniformLocation::from_i32_unchecked(2) };
 
-#[derive(Debug)]
-pub struct MeshDescription {
-    pub vertex_offset: u32,
-    pub vertex_count: u32,
-    pub triangle_offset: u32,
-    pub triangle_count: u32,
-}
-
-#[derive(Debug)]
-pub struct Mesh {
-    pub name: String,
-    pub vertices: Vec<Vector3<f32>>,
-    pub triangles: Vec<Triangle>,
-    pub bvh: bvh::bvh::Tree,
+fn print_stats(path: &std::path::Path) {
+    let scene = match bvh::scene::load(path) {
+        Ok(loaded) => loaded,
+        Err(error) => {
+            eprintln!("Failed to load {:?}: {}", path, error);
+            std::process::exit(1);
+        }
+    };
+    for mesh in scene.meshes.iter() {
+        println!("{}:", mesh.name);
+        print!("{}", mesh.bvh.view().stats());
+    }
 }
 
 fn main() {
-    let path = std::env::args_os()
-        .skip(1)
-        .next()
-        .unwrap_or(std::ffi::OsString::from("resources/sponza/sponza.obj"));
-    let (models, _materials) = tobj::load_obj(path.as_ref()).expect("Failed to load model.");
-
-    let mesh_count = models.len();
-    let meshes: Vec<Mesh> = models
-        .into_iter()
-        .enumerate()
-        .map(|(mesh_index, tobj::Model { name, mesh })| {
-            println!(
-                "Loading mesh {:?} {:03}/{:03}",
-                &name,
-                mesh_index + 1,
-                mesh_count
-            );
-
-            let vertex_count = mesh.positions.len() / 3;
-
-            let mut vertices = Vec::with_capacity(vertex_count);
-            assert_eq!(vertex_count * 3, mesh.positions.len());
-            for i in 0..vertex_count {
-                vertices.push(Vector3 {
-                    x: mesh.positions[i * 3 + 0],
-                    y: mesh.positions[i * 3 + 1],
-                    z: mesh.positions[i * 3 + 2],
-                });
+    env_logger::from_env(env_logger::Env::default().default_filter_or("warn")).init();
+
+    let mut args = std::env::args_os().skip(1);
+    let path = match args.next() {
+        Some(arg) if arg == "stats" => {
+            match args.next() {
+                Some(path) => print_stats(path.as_ref()),
+                None => {
+                    eprintln!("Usage: bvh stats <model>");
+                    std::process::exit(2);
+                }
             }
-
-            let vertex_count_u32: u32 = vertex_count.try_into().unwrap();
-            let index_count = mesh.indices.len() / 3;
-            let mut triangles = Vec::with_capacity(index_count);
-            assert_eq!(index_count * 3, mesh.indices.len());
-            for i in 0..index_count {
-                let triangle = [
-                    mesh.indices[i * 3 + 0],
-                    mesh.indices[i * 3 + 1],
-                    mesh.indices[i * 3 + 2],
-                ];
-
-                assert!(triangle[0] < vertex_count_u32);
-                assert!(triangle[1] < vertex_count_u32);
-                assert!(triangle[2] < vertex_count_u32);
-
-                triangles.push(triangle);
+            return;
+        }
+        Some(path) => path,
+        None => std::ffi::OsString::from("resources/sponza/sponza.obj"),
+    };
+    let tree_cache_path = {
+        let mut p = path.clone();
+        p.push(".trees");
+        std::path::PathBuf::from(p)
+    };
+    let bvh::scene::LoadedScene {
+        meshes,
+        materials: _,
+        instances,
+    } = match bvh::scene::load_cached(path.as_ref(), &tree_cache_path) {
+        Ok(loaded) => loaded,
+        Err(error) => {
+            eprintln!("Failed to load {:?}: {}", &path, error);
+            std::process::exit(1);
+        }
+    };
+    println!(
+        "Loaded {} meshes and {} instances from {:?}",
+        meshes.len(),
+        instances.len(),
+        &path
+    );
+
+    let camera_path_path = {
+        let mut p = path.clone();
+        p.push(".camera-path");
+        std::path::PathBuf::from(p)
+    };
+    let mut camera_path = match std::fs::File::open(&camera_path_path) {
+        Ok(file) => match bvh::camera_path::CameraPath::read(std::io::BufReader::new(file)) {
+            Ok(camera_path) => camera_path,
+            Err(error) => {
+                eprintln!("Ignoring camera path {:?}: {}", &camera_path_path, error);
+                bvh::camera_path::CameraPath::new()
             }
+        },
+        Err(_) => bvh::camera_path::CameraPath::new(),
+    };
 
-            let bvh = bvh::bvh::Tree::new(&vertices, &triangles);
-            Mesh {
-                name,
-                vertices,
-                triangles,
-                bvh,
+    let bookmarks_path = {
+        let mut p = path.clone();
+        p.push(".bookmarks");
+        std::path::PathBuf::from(p)
+    };
+    let mut bookmarks = match std::fs::File::open(&bookmarks_path) {
+        Ok(file) => match bvh::camera_bookmarks::CameraBookmarks::read(std::io::BufReader::new(file)) {
+            Ok(bookmarks) => bookmarks,
+            Err(error) => {
+                log::warn!("Ignoring camera bookmarks {:?}: {}", &bookmarks_path, error);
+                bvh::camera_bookmarks::CameraBookmarks::new()
             }
-        })
-        .collect();
+        },
+        Err(_) => bvh::camera_bookmarks::CameraBookmarks::new(),
+    };
 
-    let scene = Scene::from_meshes(&meshes);
+    let scene = bvh::scene::Scene::from_meshes(&meshes).expect("Failed to build scene.");
 
     let event_loop = glutin::event_loop::EventLoop::new();
 
@@ -266,13 +231,13 @@ fn main() {
         program
     };
 
-    let (vao, _vb, _eb) = unsafe {
+    let (vao, mesh_vb, _eb) = unsafe {
         let vao = gl.create_vertex_array();
         let vb = gl.create_buffer();
         let eb = gl.create_buffer();
 
-        gl.named_buffer_data(vb, scene.pos_in_obj_buffer.vec_as_bytes(), gl::STATIC_DRAW);
-        gl.named_buffer_data(eb, scene.triangle_buffer.vec_as_bytes(), gl::STATIC_DRAW);
+        gl.named_buffer_data(vb, scene.pos_in_obj_buffer.slice_as_bytes(), gl::STATIC_DRAW);
+        gl.named_buffer_data(eb, scene.triangle_buffer.slice_as_bytes(), gl::STATIC_DRAW);
 
         const BBI_00: gl::VertexArrayBufferBindingIndex =
             gl::VertexArrayBufferBindingIndex::from_u32(0);
@@ -292,6 +257,7 @@ fn main() {
         let vb = gl.create_buffer();
         let eb = gl.create_buffer();
 
+        #[derive(Copy, Clone)]
         #[repr(C)]
         struct Vertex {
             p0: [f32; 3],
@@ -299,6 +265,8 @@ fn main() {
             rgba: [f32; 4],
         }
 
+        unsafe impl Pod for Vertex {}
+
         let mut color_index = 0;
 
         let (vertex_buffer, mesh_node_descriptions) = meshes.iter().fold(
@@ -308,6 +276,7 @@ fn main() {
                 color_index = (color_index + 1) % RGBA_PALETTE.len();
                 let node_descriptions: Vec<u32> = mesh
                     .bvh
+                    .view()
                     .nodes
                     .iter()
                     .map(|node| {
@@ -327,8 +296,8 @@ fn main() {
 
         let point_buffer: Vec<u32> = (0u32..vertex_buffer.len().try_into().unwrap()).collect();
 
-        gl.named_buffer_data(vb, vertex_buffer.vec_as_bytes(), gl::STATIC_DRAW);
-        gl.named_buffer_data(eb, point_buffer.vec_as_bytes(), gl::STATIC_DRAW);
+        gl.named_buffer_data(vb, vertex_buffer.slice_as_bytes(), gl::STATIC_DRAW);
+        gl.named_buffer_data(eb, point_buffer.slice_as_bytes(), gl::STATIC_DRAW);
 
         const BBI_00: gl::VertexArrayBufferBindingIndex =
             gl::VertexArrayBufferBindingIndex::from_u32(0);
@@ -366,6 +335,7 @@ fn main() {
     };
 
     let start = std::time::Instant::now();
+    let mut last_frame = start;
     let mut keyboard_state = keyboard_state::KeyboardState::default();
     let mut window_state = window_state::WindowState::default();
     let mut mouse_state = mouse_state::MouseState::default();
@@ -385,8 +355,34 @@ fn main() {
         },
     };
     let mut focus_camera = false;
+    let mut orbit_camera: Option<bvh::camera::OrbitCamera> = None;
+    let mut camera_path_time: Option<f32> = None;
+    let mut walk_camera = false;
+    let mut transition: Option<(bvh::camera::TransitionCamera, bvh::camera::Camera)> = None;
+    let walk_properties = bvh::camera::WalkProperties {
+        radius: 0.3,
+        eye_height: 1.7,
+    };
     let mut current_mesh = 0;
     let mut current_depth = 0;
+    let mut show_self_intersections = false;
+    let mut self_intersection_draw = None;
+
+    fn bookmark_slot(code: glutin::event::VirtualKeyCode) -> Option<usize> {
+        use glutin::event::VirtualKeyCode;
+        match code {
+            VirtualKeyCode::F1 => Some(1),
+            VirtualKeyCode::F2 => Some(2),
+            VirtualKeyCode::F3 => Some(3),
+            VirtualKeyCode::F4 => Some(4),
+            VirtualKeyCode::F5 => Some(5),
+            VirtualKeyCode::F6 => Some(6),
+            VirtualKeyCode::F7 => Some(7),
+            VirtualKeyCode::F8 => Some(8),
+            VirtualKeyCode::F9 => Some(9),
+            _ => None,
+        }
+    }
 
     event_loop.run(move |event, _, control_flow| {
         use glutin::event::*;
@@ -413,6 +409,95 @@ fn main() {
                             focus_camera = !focus_camera;
                             windowed_context.window().set_cursor_visible(!focus_camera);
                         }
+                        (Some(VirtualKeyCode::O), ElementState::Pressed) => {
+                            orbit_camera = match orbit_camera {
+                                Some(_) => None,
+                                None => Some(bvh::camera::OrbitCamera::from_camera(&camera, 5.0)),
+                            };
+                        }
+                        (Some(VirtualKeyCode::F), ElementState::Pressed) => {
+                            // Frame the current mesh, entering orbit mode if necessary.
+                            let orbit = orbit_camera.get_or_insert_with(|| {
+                                bvh::camera::OrbitCamera::from_camera(&camera, 5.0)
+                            });
+                            let aspect =
+                                window_state.dimensions[0] as f32 / window_state.dimensions[1] as f32;
+                            let aabb = {
+                                use cgmath::*;
+                                let obj_to_wld = instances
+                                    .iter()
+                                    .find(|instance| instance.mesh_index == current_mesh)
+                                    .map_or(Matrix4::identity(), |instance| instance.obj_to_wld);
+                                let corners = meshes[current_mesh].bvh.view().nodes[0].aabb().corners();
+                                bvh::aabb::AABB3::from_points(corners.iter().map(|&corner| {
+                                    let p = obj_to_wld
+                                        .transform_point(Point3::from(Into::<[f32; 3]>::into(corner)));
+                                    bvh::vector::Vector3::from(Into::<[f32; 3]>::into(p))
+                                }))
+                                .unwrap()
+                            };
+                            orbit.frame_aabb(&aabb, aspect);
+                        }
+                        (Some(VirtualKeyCode::K), ElementState::Pressed) => {
+                            // Record a keyframe two seconds after the previous one.
+                            camera_path.push(camera.transform, 2.0);
+                            let written = std::fs::File::create(&camera_path_path)
+                                .and_then(|mut file| camera_path.write(&mut file));
+                            match written {
+                                Ok(()) => println!(
+                                    "Recorded keyframe {} to {:?}",
+                                    camera_path.keyframes.len(),
+                                    &camera_path_path
+                                ),
+                                Err(error) => eprintln!(
+                                    "Recorded keyframe {} but failed to write {:?}: {}",
+                                    camera_path.keyframes.len(),
+                                    &camera_path_path,
+                                    error
+                                ),
+                            }
+                        }
+                        (Some(VirtualKeyCode::P), ElementState::Pressed) => {
+                            camera_path_time = match camera_path_time {
+                                Some(_) => None,
+                                None => Some(0.0),
+                            };
+                        }
+                        (Some(code), ElementState::Pressed) if bookmark_slot(code).is_some() => {
+                            let slot = bookmark_slot(code).unwrap();
+                            let control = keyboard_state.lcontrol == ElementState::Pressed
+                                || keyboard_state.rcontrol == ElementState::Pressed;
+                            if control {
+                                // Ctrl+F<n> saves the current camera.
+                                bookmarks.set(slot, camera);
+                                let written = std::fs::File::create(&bookmarks_path)
+                                    .and_then(|mut file| bookmarks.write(&mut file));
+                                match written {
+                                    Ok(()) => println!("Saved bookmark {} to {:?}", slot, &bookmarks_path),
+                                    Err(error) => eprintln!(
+                                        "Set bookmark {} but failed to write {:?}: {}",
+                                        slot, &bookmarks_path, error
+                                    ),
+                                }
+                            } else if let Some(&end_camera) = bookmarks.get(slot) {
+                                // F<n> animates to the saved camera.
+                                orbit_camera = None;
+                                camera_path_time = None;
+                                let mut transition_camera = bvh::camera::TransitionCamera {
+                                    start_camera: camera,
+                                    current_camera: camera,
+                                    progress: 0.0,
+                                };
+                                transition_camera.start_transition();
+                                transition = Some((transition_camera, end_camera));
+                            }
+                        }
+                        (Some(VirtualKeyCode::G), ElementState::Pressed) => {
+                            walk_camera = !walk_camera;
+                        }
+                        (Some(VirtualKeyCode::I), ElementState::Pressed) => {
+                            show_self_intersections = !show_self_intersections;
+                        }
                         _ => {}
                     }
 
@@ -466,7 +551,17 @@ fn main() {
                 _ => {}
             },
             Event::MainEventsCleared => {
-                // Simulate.
+                // Simulate with the measured frame time. Clamp it so a stall
+                // (loading, dragging the window) doesn't teleport the camera.
+                let now = std::time::Instant::now();
+                let frame_time = (now - last_frame).as_secs_f32().min(0.1);
+                last_frame = now;
+
+                // Mouse motion is a distance rather than a rate. Divide by the
+                // frame time so the camera turns by the same amount regardless
+                // of the frame rate, with the sensitivity tuned at 60 Hz.
+                let mouse_scale = 1.0 / (60.0 * frame_time.max(1e-4));
+
                 fn key_delta(
                     n: glutin::event::ElementState,
                     p: glutin::event::ElementState,
@@ -493,7 +588,7 @@ fn main() {
                 }
 
                 let delta = bvh::camera::CameraDelta {
-                    time: 1.0 / 60.0,
+                    time: frame_time,
                     position: if window_state.focus {
                         let amp = match keyboard_state.lshift {
                             glutin::event::ElementState::Released => 1.0,
@@ -509,22 +604,69 @@ fn main() {
                         cgmath::Vector3::zero()
                     },
                     yaw: cgmath::Rad(if window_state.focus && focus_camera {
-                        -mouse_state.dx as f32
+                        -mouse_state.dx as f32 * mouse_scale
                     } else {
                         0.0
                     }),
                     pitch: cgmath::Rad(if window_state.focus && focus_camera {
-                        -mouse_state.dy as f32
+                        -mouse_state.dy as f32 * mouse_scale
                     } else {
                         0.0
                     }),
                     fovy: cgmath::Rad(if window_state.focus && focus_camera {
-                        mouse_state.dscroll as f32
+                        mouse_state.dscroll as f32 * mouse_scale
                     } else {
                         0.0
                     }),
                 };
-                camera.update(&delta);
+                match orbit_camera.as_mut() {
+                    Some(orbit) => {
+                        orbit.update(&bvh::camera::CameraDelta {
+                            // Scroll dollies instead of zooming.
+                            position: cgmath::Vector3 {
+                                z: delta.position.z + delta.fovy.0,
+                                ..delta.position
+                            },
+                            fovy: cgmath::Rad(0.0),
+                            ..delta
+                        });
+                        camera.transform = orbit.transform();
+                    }
+                    None if walk_camera => {
+                        let colliders: Vec<bvh::camera::Collider> = instances
+                            .iter()
+                            .filter_map(|instance| {
+                                let mesh = &meshes[instance.mesh_index];
+                                bvh::camera::Collider::new(mesh.bvh.view(), &mesh.vertices, instance.obj_to_wld)
+                            })
+                            .collect();
+                        camera.update_walking(&delta, &walk_properties, &colliders);
+                    }
+                    None => camera.update(&delta),
+                }
+
+                if let Some((transition_camera, end_camera)) = transition.as_mut() {
+                    transition_camera.update(bvh::camera::TransitionCameraUpdate {
+                        delta_time: frame_time,
+                        end_camera,
+                    });
+                    camera = transition_camera.current_camera;
+                    if transition_camera.progress >= 1.0 {
+                        transition = None;
+                    }
+                }
+
+                // Play back the camera path with a fixed time step so that
+                // fly-throughs are reproducible regardless of frame rate.
+                if let Some(time) = camera_path_time {
+                    match camera_path.sample(time) {
+                        Some(transform) if time <= camera_path.duration() => {
+                            camera.transform = transform;
+                            camera_path_time = Some(time + 1.0 / 60.0);
+                        }
+                        _ => camera_path_time = None,
+                    }
+                }
 
                 mouse_state.clear_motion();
 
@@ -556,30 +698,19 @@ fn main() {
                 };
 
                 let ray = {
-                    use cgmath::{InnerSpace, Rotation};
-
                     let (x, y) = if focus_camera {
                         (0.5, 0.5)
                     } else {
                         (mouse_state.x, mouse_state.y)
                     };
 
-                    bvh::ray::Ray {
-                        origin: camera.transform.position,
-                        direction: camera
-                            .transform
-                            .rot_to_parent()
-                            .cast::<f64>()
-                            .unwrap()
-                            .rotate_vector(cgmath::Vector3 {
-                                x: (1.0 - x) * frustum.x0 + x * frustum.x1,
-                                y: (1.0 - y) * frustum.y0 + y * frustum.y1,
-                                z: -1.0,
-                            })
-                            .normalize()
-                            .cast::<f32>()
-                            .unwrap(),
-                    }
+                    frustum
+                        .perspective_ray(
+                            &camera.transform.pos_to_parent().cast::<f64>().unwrap(),
+                            cgmath::Point2::new(x, y),
+                        )
+                        .cast::<f32>()
+                        .unwrap()
                 };
 
                 let cast_start = std::time::Instant::now();
@@ -588,10 +719,22 @@ fn main() {
 
                 // Cast ray, find closest hit.
                 let mut closest_t = std::f32::INFINITY;
-                let mut closest_mesh_index = 0;
-                let mut mesh_hit_node_indices = Vec::new();
+                let mut closest_instance_index = 0;
+                let mut instance_hit_node_indices = Vec::new();
+
+                for (instance_index, instance) in instances.iter().enumerate() {
+                    let mesh = &meshes[instance.mesh_index];
+
+                    // Intersect in object space. The transform is affine so
+                    // distances along the ray stay comparable between instances.
+                    let ray = {
+                        use cgmath::*;
+                        bvh::ray::Ray {
+                            origin: instance.wld_to_obj.transform_point(ray.origin),
+                            direction: instance.wld_to_obj.transform_vector(ray.direction),
+                        }
+                    };
 
-                for (mesh_index, mesh) in meshes.iter().enumerate() {
                     struct Item {
                         node_base: u32,
                         node_offset: u32,
@@ -603,8 +746,8 @@ fn main() {
                     }];
 
                     let hit_node_indices = {
-                        mesh_hit_node_indices.push(Vec::new());
-                        mesh_hit_node_indices.last_mut().unwrap()
+                        instance_hit_node_indices.push(Vec::new());
+                        instance_hit_node_indices.last_mut().unwrap()
                     };
 
                     while let Some(Item {
@@ -613,7 +756,7 @@ fn main() {
                     }) = stack.pop()
                     {
                         let node_index = node_base + node_offset;
-                        let node: &bvh::bvh::Node = &mesh.bvh.nodes[node_index as usize];
+                        let node: &bvh::bvh::Node = &mesh.bvh.view().nodes[node_index as usize];
 
                         aabb_intersection_count += 1;
                         if let Some(box_t) = bvh::intersect::ray_versus_aabb(
@@ -638,6 +781,7 @@ fn main() {
                                     // leaf.
                                     for vertex_indices in mesh
                                         .bvh
+                                        .view()
                                         .triangles
                                         .iter()
                                         .skip(node.left_or_offset as usize)
@@ -661,7 +805,7 @@ fn main() {
                                         {
                                             if tri_int.t < closest_t {
                                                 closest_t = tri_int.t;
-                                                closest_mesh_index = mesh_index;
+                                                closest_instance_index = instance_index;
                                             }
                                         }
                                     }
@@ -690,7 +834,11 @@ fn main() {
                 let wld_to_cam = camera.transform.pos_from_parent().cast::<f64>().unwrap();
                 let cam_to_clp = frustum.perspective(&range);
                 let clp_to_cam = frustum.inverse_perspective(&range);
-                let obj_to_clp = cam_to_clp * wld_to_cam;
+                let wld_to_clp = cam_to_clp * wld_to_cam;
+                let frustum_planes = frustum
+                    .perspective_planes(&wld_to_cam)
+                    .cast::<f32>()
+                    .unwrap();
 
                 unsafe {
                     gl.viewport(
@@ -718,20 +866,19 @@ fn main() {
                         gl::MajorAxis::Column,
                         clp_to_cam.cast::<f32>().unwrap().as_ref(),
                     );
-                    gl.uniform_matrix4f(
-                        OBJ_TO_CLP_LOC,
-                        gl::MajorAxis::Column,
-                        obj_to_clp.cast::<f32>().unwrap().as_ref(),
-                    );
                     gl.bind_vertex_array(vao);
 
-                    let mut color_index = 0;
+                    let set_obj_to_wld = |obj_to_wld: &cgmath::Matrix4<f32>| {
+                        let obj_to_clp = wld_to_clp.cast::<f32>().unwrap() * obj_to_wld;
+                        gl.uniform_matrix4f(OBJ_TO_CLP_LOC, gl::MajorAxis::Column, obj_to_clp.as_ref());
+                    };
 
-                    for (mesh_index, mesh) in scene.mesh_descriptions.iter().enumerate() {
+                    for (instance_index, instance) in instances.iter().enumerate() {
+                        let mesh_index = instance.mesh_index;
+                        let mesh = &scene.mesh_descriptions[mesh_index];
                         let color = {
-                            let [r, g, b, a] = RGBA_PALETTE[color_index];
-                            color_index = (color_index + 1) % RGBA_PALETTE.len();
-                            if closest_t < std::f32::INFINITY && mesh_index == closest_mesh_index {
+                            let [r, g, b, a] = RGBA_PALETTE[mesh_index % RGBA_PALETTE.len()];
+                            if closest_t < std::f32::INFINITY && instance_index == closest_instance_index {
                                 let s = (elapsed * 8.0).sin() * 0.5 + 0.5;
                                 let f = |n, m| (1.0 - s) * n + s * m;
                                 [f(r, 1.0), f(g, 0.0), f(b, 1.0), a]
@@ -744,6 +891,15 @@ fn main() {
                             // continue;
                         }
 
+                        if frustum_planes
+                            .transform(&instance.obj_to_wld)
+                            .classify(&meshes[mesh_index].bvh.view().nodes[0].aabb())
+                            == bvh::cull::Containment::Outside
+                        {
+                            continue;
+                        }
+
+                        set_obj_to_wld(&instance.obj_to_wld);
                         gl.uniform_4f(RGBA_LOC, color);
                         gl.draw_elements_base_vertex(
                             gl::TRIANGLES,
@@ -754,21 +910,114 @@ fn main() {
                         );
                     }
 
+                    if show_self_intersections {
+                        let (si_vao, _si_eb, mesh_triangle_ranges) =
+                            self_intersection_draw.get_or_insert_with(|| {
+                                let mut triangle_buffer: Vec<[u32; 3]> = Vec::new();
+                                let mesh_triangle_ranges: Vec<(u32, u32)> = meshes
+                                    .iter()
+                                    .map(|mesh| {
+                                        let offset: u32 = triangle_buffer.len().try_into().unwrap();
+                                        let intersections =
+                                            mesh.bvh.view().self_intersections(&mesh.vertices);
+                                        let mut triangle_indices =
+                                            Vec::with_capacity(intersections.len() * 2);
+                                        for intersection in intersections.iter() {
+                                            triangle_indices.push(intersection.triangles.0);
+                                            triangle_indices.push(intersection.triangles.1);
+                                        }
+                                        triangle_indices.sort();
+                                        triangle_indices.dedup();
+                                        if let Some(first) = intersections.first() {
+                                            println!(
+                                                "Mesh {:?} has {} self intersections between {} triangles, first near {:?}",
+                                                mesh.name,
+                                                intersections.len(),
+                                                triangle_indices.len(),
+                                                first.segment[0],
+                                            );
+                                        }
+                                        triangle_buffer.extend(
+                                            triangle_indices
+                                                .iter()
+                                                .map(|&i| mesh.bvh.view().triangles[i as usize]),
+                                        );
+                                        let count: u32 = triangle_buffer.len().try_into().unwrap();
+                                        (offset, count - offset)
+                                    })
+                                    .collect();
+
+                                let vao = gl.create_vertex_array();
+                                let eb = gl.create_buffer();
+                                gl.named_buffer_data(
+                                    eb,
+                                    triangle_buffer.slice_as_bytes(),
+                                    gl::STATIC_DRAW,
+                                );
+
+                                const BBI_00: gl::VertexArrayBufferBindingIndex =
+                                    gl::VertexArrayBufferBindingIndex::from_u32(0);
+
+                                gl.enable_vertex_array_attrib(vao, VS_POS_IN_OBJ_LOC);
+                                gl.vertex_array_attrib_format(
+                                    vao,
+                                    VS_POS_IN_OBJ_LOC,
+                                    3,
+                                    gl::FLOAT,
+                                    false,
+                                    0,
+                                );
+                                gl.vertex_array_attrib_binding(vao, VS_POS_IN_OBJ_LOC, BBI_00);
+                                gl.vertex_array_vertex_buffer(
+                                    vao,
+                                    BBI_00,
+                                    mesh_vb,
+                                    0,
+                                    std::mem::size_of::<[f32; 3]>() as u32,
+                                );
+                                gl.vertex_array_element_buffer(vao, eb);
+
+                                (vao, eb, mesh_triangle_ranges)
+                            });
+
+                        // Draw offending triangles on top of everything.
+                        gl.disable(gl::DEPTH_TEST);
+                        gl.disable(gl::CULL_FACE);
+                        gl.uniform_4f(RGBA_LOC, [1.0, 0.0, 0.0, 1.0]);
+                        gl.bind_vertex_array(*si_vao);
+                        for instance in instances.iter() {
+                            let mesh = &scene.mesh_descriptions[instance.mesh_index];
+                            let (triangle_offset, triangle_count) =
+                                mesh_triangle_ranges[instance.mesh_index];
+                            if triangle_count == 0 {
+                                continue;
+                            }
+                            set_obj_to_wld(&instance.obj_to_wld);
+                            gl.draw_elements_base_vertex(
+                                gl::TRIANGLES,
+                                triangle_count * 3,
+                                gl::UNSIGNED_INT,
+                                triangle_offset as usize * std::mem::size_of::<[u32; 3]>(),
+                                mesh.vertex_offset,
+                            );
+                        }
+                        gl.enable(gl::DEPTH_TEST);
+                        gl.enable(gl::CULL_FACE);
+                    }
+
                     gl.use_program(boxes_program);
                     gl.uniform_matrix4f(
                         CLP_TO_CAM_LOC,
                         gl::MajorAxis::Column,
                         clp_to_cam.cast::<f32>().unwrap().as_ref(),
                     );
-                    gl.uniform_matrix4f(
-                        OBJ_TO_CLP_LOC,
-                        gl::MajorAxis::Column,
-                        obj_to_clp.cast::<f32>().unwrap().as_ref(),
-                    );
                     gl.bind_vertex_array(boxes_vao);
 
-                    for (mesh_index, hit_node_indices) in mesh_hit_node_indices.iter().enumerate() {
-                        let node_descriptions = &mesh_node_descriptions[mesh_index];
+                    for (instance, hit_node_indices) in
+                        instances.iter().zip(instance_hit_node_indices.iter())
+                    {
+                        let node_descriptions = &mesh_node_descriptions[instance.mesh_index];
+                        set_obj_to_wld(&instance.obj_to_wld);
 
                         for &node_index in hit_node_indices.iter() {
                             let offset = node_descriptions[node_index as usize] as usize
@@ -0,0 +1,406 @@
+use std::convert::TryInto;
+use std::io::BufRead;
+
+use crate::bvh::*;
+use crate::vector::*;
+
+#[derive(Debug)]
+pub enum PlyError {
+    Io(std::io::Error),
+    /// The header is malformed, lines are numbered from 1.
+    Header { line: usize },
+    /// No vertex or no face element was declared.
+    MissingElement(&'static str),
+    /// The vertex element lacks one of the x, y and z properties, or the face
+    /// element lacks a vertex index list.
+    MissingProperty(&'static str),
+    /// The body could not be parsed or ended early.
+    Body,
+    IndexOutOfRange { index: u32 },
+}
+
+impl std::fmt::Display for PlyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PlyError::Io(error) => write!(f, "{}", error),
+            PlyError::Header { line } => write!(f, "invalid header on line {}", line),
+            PlyError::MissingElement(name) => write!(f, "missing element {:?}", name),
+            PlyError::MissingProperty(name) => write!(f, "missing property {:?}", name),
+            PlyError::Body => write!(f, "invalid or truncated element data"),
+            PlyError::IndexOutOfRange { index } => write!(f, "vertex index {} out of range", index),
+        }
+    }
+}
+
+impl std::error::Error for PlyError {}
+
+impl From<std::io::Error> for PlyError {
+    fn from(error: std::io::Error) -> Self {
+        PlyError::Io(error)
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "char" | "int8" => Scalar::I8,
+            "uchar" | "uint8" => Scalar::U8,
+            "short" | "int16" => Scalar::I16,
+            "ushort" | "uint16" => Scalar::U16,
+            "int" | "int32" => Scalar::I32,
+            "uint" | "uint32" => Scalar::U32,
+            "float" | "float32" => Scalar::F32,
+            "double" | "float64" => Scalar::F64,
+            _ => return None,
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Scalar::I8 | Scalar::U8 => 1,
+            Scalar::I16 | Scalar::U16 => 2,
+            Scalar::I32 | Scalar::U32 | Scalar::F32 => 4,
+            Scalar::F64 => 8,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Property {
+    Scalar { scalar: Scalar, name: String },
+    List { count: Scalar, item: Scalar, name: String },
+}
+
+#[derive(Debug)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+/// Reads the scalars in the body, converting them to `f64` which represents
+/// every PLY scalar type exactly.
+enum Values<'a> {
+    Ascii(std::str::SplitAsciiWhitespace<'a>),
+    Binary { bytes: &'a [u8], big_endian: bool },
+}
+
+impl<'a> Values<'a> {
+    fn read(&mut self, scalar: Scalar) -> Result<f64, PlyError> {
+        match self {
+            Values::Ascii(words) => words
+                .next()
+                .and_then(|word| word.parse::<f64>().ok())
+                .ok_or(PlyError::Body),
+            Values::Binary { bytes, big_endian } => {
+                let size = scalar.size();
+                if bytes.len() < size {
+                    return Err(PlyError::Body);
+                }
+                let (head, tail) = bytes.split_at(size);
+                *bytes = tail;
+
+                macro_rules! convert {
+                    ($T: ty) => {{
+                        let array = head.try_into().unwrap();
+                        (if *big_endian {
+                            <$T>::from_be_bytes(array)
+                        } else {
+                            <$T>::from_le_bytes(array)
+                        }) as f64
+                    }};
+                }
+
+                Ok(match scalar {
+                    Scalar::I8 => convert!(i8),
+                    Scalar::U8 => convert!(u8),
+                    Scalar::I16 => convert!(i16),
+                    Scalar::U16 => convert!(u16),
+                    Scalar::I32 => convert!(i32),
+                    Scalar::U32 => convert!(u32),
+                    Scalar::F32 => convert!(f32),
+                    Scalar::F64 => convert!(f64),
+                })
+            }
+        }
+    }
+}
+
+fn read_header<R: BufRead>(reader: &mut R) -> Result<(Format, Vec<Element>), PlyError> {
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    let mut line = String::new();
+
+    for line_number in 1.. {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(PlyError::Header { line: line_number });
+        }
+        let error = PlyError::Header { line: line_number };
+        let words: Vec<&str> = line.split_whitespace().collect();
+
+        match (line_number, words.as_slice()) {
+            (1, ["ply"]) => {}
+            (1, _) => return Err(error),
+            (_, ["format", name, "1.0"]) => {
+                format = Some(match *name {
+                    "ascii" => Format::Ascii,
+                    "binary_little_endian" => Format::BinaryLittleEndian,
+                    "binary_big_endian" => Format::BinaryBigEndian,
+                    _ => return Err(error),
+                });
+            }
+            (_, ["comment", ..]) | (_, ["obj_info", ..]) | (_, []) => {}
+            (_, ["element", name, count]) => elements.push(Element {
+                name: name.to_string(),
+                count: count.parse().map_err(|_| PlyError::Header { line: line_number })?,
+                properties: Vec::new(),
+            }),
+            (_, ["property", "list", count, item, name]) => {
+                let property = Property::List {
+                    count: Scalar::parse(count).ok_or(PlyError::Header { line: line_number })?,
+                    item: Scalar::parse(item).ok_or(PlyError::Header { line: line_number })?,
+                    name: name.to_string(),
+                };
+                elements.last_mut().ok_or(error)?.properties.push(property);
+            }
+            (_, ["property", scalar, name]) => {
+                let property = Property::Scalar {
+                    scalar: Scalar::parse(scalar).ok_or(PlyError::Header { line: line_number })?,
+                    name: name.to_string(),
+                };
+                elements.last_mut().ok_or(error)?.properties.push(property);
+            }
+            (_, ["end_header"]) => break,
+            _ => return Err(error),
+        }
+    }
+
+    Ok((format.ok_or(PlyError::Header { line: 1 })?, elements))
+}
+
+/// Reads an ASCII or binary PLY file. Polygons are triangulated as fans.
+/// Vertex properties other than the position and elements other than
+/// `vertex` and `face` are skipped.
+pub fn read_ply<R: BufRead>(mut reader: R) -> Result<(Vec<Vector3<f32>>, Vec<Triangle>), PlyError> {
+    let (format, elements) = read_header(&mut reader)?;
+
+    let vertex_element = elements
+        .iter()
+        .find(|element| element.name == "vertex")
+        .ok_or(PlyError::MissingElement("vertex"))?;
+    for &axis in ["x", "y", "z"].iter() {
+        let found = vertex_element.properties.iter().any(|property| match property {
+            Property::Scalar { name, .. } => name == axis,
+            _ => false,
+        });
+        if !found {
+            return Err(PlyError::MissingProperty(axis));
+        }
+    }
+    let face_element = elements
+        .iter()
+        .find(|element| element.name == "face")
+        .ok_or(PlyError::MissingElement("face"))?;
+    let has_indices = face_element.properties.iter().any(|property| match property {
+        Property::List { name, .. } => name == "vertex_indices" || name == "vertex_index",
+        _ => false,
+    });
+    if !has_indices {
+        return Err(PlyError::MissingProperty("vertex_indices"));
+    }
+
+    let mut body = Vec::new();
+    reader.read_to_end(&mut body)?;
+    let mut values = match format {
+        Format::Ascii => Values::Ascii(
+            std::str::from_utf8(&body)
+                .map_err(|_| PlyError::Body)?
+                .split_ascii_whitespace(),
+        ),
+        Format::BinaryLittleEndian => Values::Binary {
+            bytes: &body,
+            big_endian: false,
+        },
+        Format::BinaryBigEndian => Values::Binary {
+            bytes: &body,
+            big_endian: true,
+        },
+    };
+
+    let mut vertices = Vec::new();
+    let mut triangles = Vec::new();
+    let mut polygon = Vec::new();
+
+    for element in elements.iter() {
+        for _ in 0..element.count {
+            let mut position = [0.0f32; 3];
+            polygon.clear();
+
+            for property in element.properties.iter() {
+                match property {
+                    Property::Scalar { scalar, name } => {
+                        let value = values.read(*scalar)?;
+                        match name.as_str() {
+                            "x" => position[0] = value as f32,
+                            "y" => position[1] = value as f32,
+                            "z" => position[2] = value as f32,
+                            _ => {}
+                        }
+                    }
+                    Property::List { count, item, name } => {
+                        // ASCII values are parsed as floats, so counts and
+                        // indices can be NaN, infinite or fractional.
+                        let count = values.read(*count)?;
+                        if count.is_nan() || count < 0.0 || count.fract() != 0.0 {
+                            return Err(PlyError::Body);
+                        }
+                        let is_indices = name == "vertex_indices" || name == "vertex_index";
+                        for _ in 0..count as usize {
+                            let value = values.read(*item)?;
+                            if is_indices {
+                                if value.is_nan() || value < 0.0 || value > u32::MAX as f64 || value.fract() != 0.0 {
+                                    return Err(PlyError::Body);
+                                }
+                                polygon.push(value as u32);
+                            }
+                        }
+                    }
+                }
+            }
+
+            match element.name.as_str() {
+                "vertex" => vertices.push(Vector3::from(position)),
+                "face" => {
+                    for i in 2..polygon.len() {
+                        triangles.push([polygon[0], polygon[i - 1], polygon[i]]);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    if let Some(&index) = triangles
+        .iter()
+        .flat_map(|triangle| triangle.iter())
+        .find(|&&index| index as usize >= vertices.len())
+    {
+        return Err(PlyError::IndexOutOfRange { index });
+    }
+
+    Ok((vertices, triangles))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_ascii_and_binary() {
+        let ascii = b"ply
+format ascii 1.0
+comment a unit quad
+element vertex 4
+property float x
+property float y
+property float z
+property uchar red
+element face 1
+property list uchar int vertex_indices
+end_header
+0 0 0 255
+1 0 0 255
+1 1 0 255
+0 1 0 255
+4 0 1 2 3
+";
+        let (vertices, triangles) = read_ply(&ascii[..]).unwrap();
+        assert_eq!(4, vertices.len());
+        assert_eq!(vec![[0, 1, 2], [0, 2, 3]], triangles);
+
+        let mut binary = b"ply
+format binary_big_endian 1.0
+element vertex 3
+property double x
+property double y
+property double z
+element face 1
+property list uchar uint vertex_index
+end_header
+"
+        .to_vec();
+        for &f in [0.0f64, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 2.0, 0.0].iter() {
+            binary.extend_from_slice(&f.to_be_bytes());
+        }
+        binary.push(3);
+        for &i in [0u32, 1, 2].iter() {
+            binary.extend_from_slice(&i.to_be_bytes());
+        }
+        let (vertices, triangles) = read_ply(&binary[..]).unwrap();
+        assert_eq!(2.0, vertices[2].y);
+        assert_eq!(vec![[0, 1, 2]], triangles);
+
+        // Truncated body.
+        assert!(read_ply(&binary[..binary.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn missing_elements_and_properties() {
+        let read = |header: &str| read_ply(format!("ply\nformat ascii 1.0\n{}end_header\n", header).as_bytes());
+        let vertex = "element vertex 0\nproperty float x\nproperty float y\nproperty float z\n";
+        let face = "element face 0\nproperty list uchar int vertex_indices\n";
+
+        assert!(matches!(read(face), Err(PlyError::MissingElement("vertex"))));
+        assert!(matches!(read(vertex), Err(PlyError::MissingElement("face"))));
+        assert!(matches!(
+            read(&format!("element vertex 0\nproperty float x\nproperty float y\n{}", face)),
+            Err(PlyError::MissingProperty("z"))
+        ));
+        assert!(matches!(
+            read(&format!("{}element face 0\nproperty uchar flags\n", vertex)),
+            Err(PlyError::MissingProperty("vertex_indices"))
+        ));
+        assert!(read(&format!("{}{}", vertex, face)).is_ok());
+    }
+
+    #[test]
+    fn invalid_ascii_counts_and_indices() {
+        let read = |faces: &str| {
+            read_ply(
+                format!(
+                    "ply\nformat ascii 1.0\n\
+                     element vertex 3\nproperty float x\nproperty float y\nproperty float z\n\
+                     element face 1\nproperty list uchar int vertex_indices\nend_header\n\
+                     0 0 0\n1 0 0\n0 1 0\n{}\n",
+                    faces
+                )
+                .as_bytes(),
+            )
+        };
+
+        assert!(read("3 0 1 2").is_ok());
+        for faces in &["nan 0 1 2", "inf 0 1 2", "-3 0 1 2", "2.5 0 1 2", "3 0 nan 2", "3 0 0.5 2", "3 0 1 -inf"] {
+            assert!(matches!(read(faces), Err(PlyError::Body)), "{}", faces);
+        }
+    }
+}
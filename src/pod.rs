@@ -0,0 +1,97 @@
+use crate::bvh::*;
+use crate::vector::*;
+
+/// Plain old data: types without padding or pointers for which every bit
+/// pattern is a valid value. Only these can be viewed as bytes and created
+/// from bytes.
+///
+/// # Safety
+///
+/// Implementors must be `#[repr(C)]` (or `#[repr(transparent)]`), contain
+/// only `Pod` fields, have no padding, and accept any bit pattern.
+pub unsafe trait Pod: Copy + 'static {}
+
+macro_rules! impl_pod {
+    ($($T: ty),*) => {
+        $(unsafe impl Pod for $T {})*
+    };
+}
+
+impl_pod!(u8, i8, u16, i16, u32, i32, u64, i64, f32, f64);
+
+unsafe impl<T: Pod, const N: usize> Pod for [T; N] {}
+
+unsafe impl<T: Pod> Pod for Vector3<T> {}
+
+unsafe impl Pod for Node {}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum CastError {
+    /// The bytes do not start at a multiple of the alignment of the type.
+    Misaligned,
+    /// The number of bytes is not a multiple of the size of the type.
+    Size,
+}
+
+impl std::fmt::Display for CastError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CastError::Misaligned => write!(f, "bytes are not aligned for the target type"),
+            CastError::Size => write!(f, "byte count is not a multiple of the target type size"),
+        }
+    }
+}
+
+impl std::error::Error for CastError {}
+
+pub trait ValueAsBytes {
+    fn value_as_bytes(&self) -> &[u8];
+}
+
+impl<T: Pod> ValueAsBytes for T {
+    #[inline]
+    fn value_as_bytes(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self as *const T as *const u8, std::mem::size_of::<T>()) }
+    }
+}
+
+pub trait SliceAsBytes {
+    fn slice_as_bytes(&self) -> &[u8];
+}
+
+impl<T: Pod> SliceAsBytes for [T] {
+    #[inline]
+    fn slice_as_bytes(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.as_ptr() as *const u8, std::mem::size_of_val(self)) }
+    }
+}
+
+/// Views bytes as a slice of `T`, checking alignment and size. Values are
+/// read in native byte order.
+pub fn cast_slice<T: Pod>(bytes: &[u8]) -> Result<&[T], CastError> {
+    let size = std::mem::size_of::<T>();
+    if (bytes.as_ptr() as usize) % std::mem::align_of::<T>() != 0 {
+        return Err(CastError::Misaligned);
+    }
+    if size == 0 || bytes.len() % size != 0 {
+        return Err(CastError::Size);
+    }
+    Ok(unsafe { std::slice::from_raw_parts(bytes.as_ptr() as *const T, bytes.len() / size) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cast_round_trip() {
+        let triangles: Vec<Triangle> = vec![[0, 1, 2], [2, 1, 3]];
+        let bytes = triangles.slice_as_bytes();
+        assert_eq!(24, bytes.len());
+        assert_eq!(&triangles[..], cast_slice::<Triangle>(bytes).unwrap());
+
+        assert_eq!(Err(CastError::Size), cast_slice::<Triangle>(&bytes[..20]));
+        assert_eq!(Err(CastError::Misaligned), cast_slice::<u32>(&bytes[1..5]));
+        assert_eq!(&2u32.to_ne_bytes()[..], 2u32.value_as_bytes());
+    }
+}
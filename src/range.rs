@@ -11,6 +11,20 @@ pub struct Range3<T> {
 }
 
 impl<T: Float> Range3<T> {
+    /// Clip space with x and y in [-1, 1] and depth in [0, 1], where the far
+    /// plane maps to 0 and the near plane to 1.
+    #[inline]
+    pub fn reversed_z() -> Self {
+        Self {
+            x0: -T::one(),
+            x1: T::one(),
+            y0: -T::one(),
+            y1: T::one(),
+            z0: T::zero(),
+            z1: T::one(),
+        }
+    }
+
     #[inline]
     pub fn dx(&self) -> T {
         self.x1 - self.x0
@@ -1,7 +1,22 @@
 use cgmath::*;
 
 #[derive(Debug, Copy, Clone)]
-pub struct Ray {
-    pub origin: Point3<f32>,
-    pub direction: Vector3<f32>,
+pub struct Ray<T = f32> {
+    pub origin: Point3<T>,
+    pub direction: Vector3<T>,
+}
+
+impl<T: BaseFloat> Ray<T> {
+    #[inline]
+    pub fn at(&self, t: T) -> Point3<T> {
+        self.origin + self.direction * t
+    }
+
+    #[inline]
+    pub fn cast<U: BaseFloat>(&self) -> Option<Ray<U>> {
+        Some(Ray {
+            origin: self.origin.cast()?,
+            direction: self.direction.cast()?,
+        })
+    }
 }
@@ -0,0 +1,980 @@
+use cgmath::{Matrix4, SquareMatrix};
+use std::convert::TryInto;
+use std::path::{Path, PathBuf};
+
+use crate::bvh::*;
+use crate::hash::*;
+use crate::intersect::*;
+use crate::ply::*;
+use crate::ray::*;
+use crate::stl::*;
+use crate::tree_file::*;
+use crate::vector::*;
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io(std::io::Error),
+    Load(tobj::LoadError),
+    Gltf(gltf::Error),
+    Ply(PlyError),
+    Stl(StlError),
+    /// The number of position coordinates is not a multiple of 3.
+    PositionCount { mesh: String },
+    /// The number of indices is not a multiple of 3.
+    NonTriangularFaces { mesh: String },
+    IndexOutOfRange {
+        mesh: String,
+        index: u32,
+        vertex_count: usize,
+    },
+    /// A per-vertex attribute does not have one value per vertex.
+    AttributeCount {
+        mesh: String,
+        attribute: &'static str,
+    },
+    /// A required per-vertex attribute is missing.
+    MissingAttribute {
+        mesh: String,
+        attribute: &'static str,
+    },
+    /// Meshes without triangles can not be put in a tree.
+    EmptyMesh { mesh: String },
+    /// Scenes are indexed with `u32`.
+    TooLarge,
+    TreeFile(TreeFileError),
+}
+
+impl std::fmt::Display for SceneError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SceneError::Io(error) => write!(f, "{}", error),
+            SceneError::Load(error) => write!(f, "failed to load model: {}", error),
+            SceneError::Gltf(error) => write!(f, "failed to load glTF: {}", error),
+            SceneError::Ply(error) => write!(f, "failed to load PLY: {}", error),
+            SceneError::Stl(error) => write!(f, "failed to load STL: {}", error),
+            SceneError::PositionCount { mesh } => {
+                write!(f, "mesh {:?}: position count is not a multiple of 3", mesh)
+            }
+            SceneError::NonTriangularFaces { mesh } => {
+                write!(f, "mesh {:?}: index count is not a multiple of 3", mesh)
+            }
+            SceneError::IndexOutOfRange {
+                mesh,
+                index,
+                vertex_count,
+            } => write!(
+                f,
+                "mesh {:?}: index {} out of range for {} vertices",
+                mesh, index, vertex_count
+            ),
+            SceneError::AttributeCount { mesh, attribute } => {
+                write!(f, "mesh {:?}: {} count does not match the vertex count", mesh, attribute)
+            }
+            SceneError::MissingAttribute { mesh, attribute } => {
+                write!(f, "mesh {:?}: missing {} attribute", mesh, attribute)
+            }
+            SceneError::EmptyMesh { mesh } => write!(f, "mesh {:?} has no triangles", mesh),
+            SceneError::TooLarge => write!(f, "scene does not fit in 32 bit indices"),
+            SceneError::TreeFile(error) => write!(f, "tree cache: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SceneError::Io(error) => Some(error),
+            SceneError::Load(error) => Some(error),
+            SceneError::Gltf(error) => Some(error),
+            SceneError::Ply(error) => Some(error),
+            SceneError::Stl(error) => Some(error),
+            SceneError::TreeFile(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for SceneError {
+    fn from(error: std::io::Error) -> Self {
+        SceneError::Io(error)
+    }
+}
+
+impl From<tobj::LoadError> for SceneError {
+    fn from(error: tobj::LoadError) -> Self {
+        SceneError::Load(error)
+    }
+}
+
+impl From<gltf::Error> for SceneError {
+    fn from(error: gltf::Error) -> Self {
+        SceneError::Gltf(error)
+    }
+}
+
+impl From<PlyError> for SceneError {
+    fn from(error: PlyError) -> Self {
+        SceneError::Ply(error)
+    }
+}
+
+impl From<StlError> for SceneError {
+    fn from(error: StlError) -> Self {
+        SceneError::Stl(error)
+    }
+}
+
+impl From<TreeFileError> for SceneError {
+    fn from(error: TreeFileError) -> Self {
+        SceneError::TreeFile(error)
+    }
+}
+
+/// A mesh's tree, either built in memory or mapped from a cached tree file.
+#[derive(Debug)]
+pub enum MeshTree {
+    Built(Tree),
+    Mapped(MappedTree),
+}
+
+impl MeshTree {
+    #[inline]
+    pub fn view(&self) -> TreeView<'_> {
+        match self {
+            MeshTree::Built(tree) => tree.view(),
+            MeshTree::Mapped(tree) => tree.view(),
+        }
+    }
+}
+
+/// Produces the tree for a mesh's vertices and triangles, `build_tree` unless
+/// the trees come from somewhere else like a cache.
+pub type BuildTree<'a> = dyn FnMut(&[Vector3<f32>], &[Triangle]) -> MeshTree + 'a;
+
+/// Builds the tree in memory with `Tree::new`.
+pub fn build_tree(vertices: &[Vector3<f32>], triangles: &[Triangle]) -> MeshTree {
+    MeshTree::Built(Tree::new(vertices, triangles))
+}
+
+#[derive(Debug, Clone)]
+pub struct Material {
+    pub name: String,
+    pub ambient: [f32; 3],
+    pub diffuse: [f32; 3],
+    pub specular: [f32; 3],
+    pub shininess: f32,
+    pub dissolve: f32,
+    pub diffuse_texture: Option<String>,
+}
+
+impl From<tobj::Material> for Material {
+    fn from(material: tobj::Material) -> Self {
+        Material {
+            name: material.name,
+            ambient: material.ambient,
+            diffuse: material.diffuse,
+            specular: material.specular,
+            shininess: material.shininess,
+            dissolve: material.dissolve,
+            diffuse_texture: Some(material.diffuse_texture).filter(|texture| !texture.is_empty()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Mesh {
+    pub name: String,
+    pub vertices: Vec<Vector3<f32>>,
+    pub triangles: Vec<Triangle>,
+    pub bvh: MeshTree,
+    /// Per-vertex shading normals.
+    pub normals: Option<Vec<Vector3<f32>>>,
+    /// Per-vertex texture coordinates.
+    pub texcoords: Option<Vec<[f32; 2]>>,
+    /// Index into the materials loaded alongside the mesh.
+    pub material_index: Option<usize>,
+}
+
+/// A placement of a mesh in the world. Several instances can share a mesh
+/// and its tree.
+#[derive(Debug, Copy, Clone)]
+pub struct Instance {
+    pub mesh_index: usize,
+    pub obj_to_wld: Matrix4<f32>,
+    pub wld_to_obj: Matrix4<f32>,
+}
+
+impl Instance {
+    /// Returns `None` when the transform cannot be inverted, for example
+    /// because it scales an axis to zero.
+    #[inline]
+    pub fn new(mesh_index: usize, obj_to_wld: Matrix4<f32>) -> Option<Self> {
+        obj_to_wld.invert().map(|wld_to_obj| Instance {
+            mesh_index,
+            obj_to_wld,
+            wld_to_obj,
+        })
+    }
+
+    #[inline]
+    pub fn identity(mesh_index: usize) -> Self {
+        Instance {
+            mesh_index,
+            obj_to_wld: Matrix4::identity(),
+            wld_to_obj: Matrix4::identity(),
+        }
+    }
+}
+
+/// The attributes of a mesh at a ray hit.
+#[derive(Debug, Copy, Clone)]
+pub struct SurfacePoint {
+    pub position: Vector3<f32>,
+    /// Normalized interpolated vertex normal, or the geometric normal when
+    /// the mesh has no normals.
+    pub normal: Vector3<f32>,
+    pub texcoord: Option<[f32; 2]>,
+}
+
+impl Mesh {
+    /// Builds a mesh and its tree from flat position coordinates and
+    /// triangle vertex indices.
+    #[inline]
+    pub fn new(name: String, positions: &[f32], indices: &[u32]) -> Result<Self, SceneError> {
+        Self::new_with(name, positions, indices, &mut build_tree)
+    }
+
+    fn new_with(
+        name: String,
+        positions: &[f32],
+        indices: &[u32],
+        build: &mut BuildTree,
+    ) -> Result<Self, SceneError> {
+        if positions.len() % 3 != 0 {
+            return Err(SceneError::PositionCount { mesh: name });
+        }
+        if indices.len() % 3 != 0 {
+            return Err(SceneError::NonTriangularFaces { mesh: name });
+        }
+
+        let vertices: Vec<Vector3<f32>> = positions
+            .chunks_exact(3)
+            .map(|p| Vector3::from([p[0], p[1], p[2]]))
+            .collect();
+        let triangles: Vec<Triangle> = indices.chunks_exact(3).map(|t| [t[0], t[1], t[2]]).collect();
+
+        Self::from_triangles_with(name, vertices, triangles, build)
+    }
+
+    /// Builds a mesh and its tree from vertices and triangles, like the ones
+    /// produced by the PLY and STL readers.
+    #[inline]
+    pub fn from_triangles(
+        name: String,
+        vertices: Vec<Vector3<f32>>,
+        triangles: Vec<Triangle>,
+    ) -> Result<Self, SceneError> {
+        Self::from_triangles_with(name, vertices, triangles, &mut build_tree)
+    }
+
+    fn from_triangles_with(
+        name: String,
+        vertices: Vec<Vector3<f32>>,
+        triangles: Vec<Triangle>,
+        build: &mut BuildTree,
+    ) -> Result<Self, SceneError> {
+        if triangles.is_empty() {
+            return Err(SceneError::EmptyMesh { mesh: name });
+        }
+
+        let vertex_count = vertices.len();
+        if let Some(&index) = triangles
+            .iter()
+            .flat_map(|triangle| triangle.iter())
+            .find(|&&index| index as usize >= vertex_count)
+        {
+            return Err(SceneError::IndexOutOfRange {
+                mesh: name,
+                index,
+                vertex_count,
+            });
+        }
+
+        let bvh = build(&vertices, &triangles);
+        Ok(Mesh {
+            name,
+            vertices,
+            triangles,
+            bvh,
+            normals: None,
+            texcoords: None,
+            material_index: None,
+        })
+    }
+
+    /// Attaches flat per-vertex normal coordinates. Empty input leaves the
+    /// mesh without normals.
+    pub fn with_normals(mut self, normals: &[f32]) -> Result<Self, SceneError> {
+        if normals.is_empty() {
+            return Ok(self);
+        }
+        if normals.len() != self.vertices.len() * 3 {
+            return Err(SceneError::AttributeCount {
+                mesh: self.name,
+                attribute: "normal",
+            });
+        }
+        self.normals = Some(
+            normals
+                .chunks_exact(3)
+                .map(|n| Vector3::from([n[0], n[1], n[2]]))
+                .collect(),
+        );
+        Ok(self)
+    }
+
+    /// Attaches flat per-vertex texture coordinates. Empty input leaves the
+    /// mesh without texture coordinates.
+    pub fn with_texcoords(mut self, texcoords: &[f32]) -> Result<Self, SceneError> {
+        if texcoords.is_empty() {
+            return Ok(self);
+        }
+        if texcoords.len() != self.vertices.len() * 2 {
+            return Err(SceneError::AttributeCount {
+                mesh: self.name,
+                attribute: "texture coordinate",
+            });
+        }
+        self.texcoords = Some(texcoords.chunks_exact(2).map(|t| [t[0], t[1]]).collect());
+        Ok(self)
+    }
+
+    #[inline]
+    pub fn with_material_index(mut self, material_index: Option<usize>) -> Self {
+        self.material_index = material_index;
+        self
+    }
+
+    #[inline]
+    pub fn ray_hit(&self, ray: Ray, max_t: f32) -> Option<RayHit> {
+        self.bvh.view().ray_hit(&self.vertices, ray, max_t)
+    }
+
+    /// Interpolates the vertex attributes at the hit using its barycentric
+    /// coordinates, where `w`, `u` and `v` weigh the first, second and third
+    /// vertex of the triangle.
+    pub fn surface_point(&self, hit: &RayHit) -> SurfacePoint {
+        let [i0, i1, i2] = self.bvh.view().triangles[hit.triangle_index as usize];
+        let (i0, i1, i2) = (i0 as usize, i1 as usize, i2 as usize);
+        let TriangleIntersection { u, v, w, .. } = hit.intersection;
+
+        let [p0, p1, p2] = [self.vertices[i0], self.vertices[i1], self.vertices[i2]];
+        let position = p0 * w + p1 * u + p2 * v;
+
+        let normal = match self.normals.as_ref() {
+            Some(normals) => normals[i0] * w + normals[i1] * u + normals[i2] * v,
+            None => (p1 - p0).cross(p2 - p0),
+        };
+        let magnitude = normal.magnitude();
+        let normal = if magnitude > 0.0 { normal / magnitude } else { normal };
+
+        let texcoord = self.texcoords.as_ref().map(|texcoords| {
+            let [t0, t1, t2] = [texcoords[i0], texcoords[i1], texcoords[i2]];
+            [
+                t0[0] * w + t1[0] * u + t2[0] * v,
+                t0[1] * w + t1[1] * u + t2[1] * v,
+            ]
+        });
+
+        SurfacePoint {
+            position,
+            normal,
+            texcoord,
+        }
+    }
+}
+
+/// Meshes, the materials they refer to and their placements in the world.
+#[derive(Debug)]
+pub struct LoadedScene {
+    pub meshes: Vec<Mesh>,
+    pub materials: Vec<Material>,
+    pub instances: Vec<Instance>,
+}
+
+/// Picks the loader from the file extension: glTF for `gltf` and `glb`, PLY
+/// for `ply`, STL for `stl` and OBJ otherwise.
+#[inline]
+pub fn load(path: &Path) -> Result<LoadedScene, SceneError> {
+    load_with(path, &mut build_tree)
+}
+
+/// Like `load` but obtains the trees from `build`, which is called once for
+/// every mesh in order.
+pub fn load_with(path: &Path, build: &mut BuildTree) -> Result<LoadedScene, SceneError> {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("gltf") | Some("glb") => load_gltf_with(path, build),
+        Some("ply") => {
+            let file = std::fs::File::open(path)?;
+            load_single(path, read_ply(std::io::BufReader::new(file))?, build)
+        }
+        Some("stl") => load_single(path, read_stl(std::fs::File::open(path)?)?, build),
+        _ => load_obj_with(path, build),
+    }
+}
+
+/// Wraps the single mesh stored in a PLY or STL file.
+fn load_single(
+    path: &Path,
+    (vertices, triangles): (Vec<Vector3<f32>>, Vec<Triangle>),
+    build: &mut BuildTree,
+) -> Result<LoadedScene, SceneError> {
+    let name = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    Ok(LoadedScene {
+        meshes: vec![Mesh::from_triangles_with(name, vertices, triangles, build)?],
+        materials: Vec::new(),
+        instances: vec![Instance::identity(0)],
+    })
+}
+
+/// Loads every model in the OBJ file as a mesh with a single untransformed
+/// instance, along with the materials they refer to. Faces are triangulated
+/// by the OBJ reader.
+#[inline]
+pub fn load_obj(path: &Path) -> Result<LoadedScene, SceneError> {
+    load_obj_with(path, &mut build_tree)
+}
+
+fn load_obj_with(path: &Path, build: &mut BuildTree) -> Result<LoadedScene, SceneError> {
+    let (models, materials) = tobj::load_obj(path)?;
+    let meshes = models
+        .into_iter()
+        .map(|tobj::Model { name, mesh }| {
+            Ok(Mesh::new_with(name, &mesh.positions, &mesh.indices, build)?
+                .with_normals(&mesh.normals)?
+                .with_texcoords(&mesh.texcoords)?
+                .with_material_index(mesh.material_id))
+        })
+        .collect::<Result<Vec<Mesh>, SceneError>>()?;
+    let materials = materials.into_iter().map(Material::from).collect();
+    let instances = (0..meshes.len()).map(Instance::identity).collect();
+    Ok(LoadedScene {
+        meshes,
+        materials,
+        instances,
+    })
+}
+
+fn flatten<A: AsRef<[f32]>>(values: impl Iterator<Item = A>) -> Vec<f32> {
+    let mut flat = Vec::new();
+    for value in values {
+        flat.extend_from_slice(value.as_ref());
+    }
+    flat
+}
+
+/// Loads a `.gltf` or `.glb` file. Every triangle primitive becomes a mesh
+/// with its own tree, and every node of the default scene (or the first
+/// scene) that refers to a glTF mesh adds an instance of its primitives with
+/// the node's world transform. Other primitive modes are skipped.
+#[inline]
+pub fn load_gltf(path: &Path) -> Result<LoadedScene, SceneError> {
+    load_gltf_with(path, &mut build_tree)
+}
+
+fn load_gltf_with(path: &Path, build: &mut BuildTree) -> Result<LoadedScene, SceneError> {
+    let (document, buffers, _images) = gltf::import(path)?;
+
+    let mut meshes = Vec::new();
+    // The scene meshes created for each glTF mesh.
+    let mut mesh_indices: Vec<Vec<usize>> = Vec::new();
+
+    for gltf_mesh in document.meshes() {
+        let mut indices = Vec::new();
+        for primitive in gltf_mesh.primitives() {
+            if primitive.mode() != gltf::mesh::Mode::Triangles {
+                continue;
+            }
+
+            let name = match gltf_mesh.name() {
+                Some(name) => format!("{}/{}", name, primitive.index()),
+                None => format!("mesh{}/{}", gltf_mesh.index(), primitive.index()),
+            };
+            let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+
+            let positions = match reader.read_positions() {
+                Some(positions) => flatten(positions),
+                None => {
+                    return Err(SceneError::MissingAttribute {
+                        mesh: name,
+                        attribute: "position",
+                    })
+                }
+            };
+            let triangle_indices: Vec<u32> = match reader.read_indices() {
+                Some(triangle_indices) => triangle_indices.into_u32().collect(),
+                None => (0..(positions.len() / 3) as u32).collect(),
+            };
+            let normals = reader.read_normals().map(flatten).unwrap_or_default();
+            let texcoords = reader
+                .read_tex_coords(0)
+                .map(|texcoords| flatten(texcoords.into_f32()))
+                .unwrap_or_default();
+
+            indices.push(meshes.len());
+            meshes.push(
+                Mesh::new_with(name, &positions, &triangle_indices, build)?
+                    .with_normals(&normals)?
+                    .with_texcoords(&texcoords)?
+                    .with_material_index(primitive.material().index()),
+            );
+        }
+        mesh_indices.push(indices);
+    }
+
+    let mut instances = Vec::new();
+    if let Some(scene) = document.default_scene().or_else(|| document.scenes().next()) {
+        let mut stack: Vec<(gltf::Node, Matrix4<f32>)> =
+            scene.nodes().map(|node| (node, Matrix4::identity())).collect();
+        while let Some((node, parent_to_wld)) = stack.pop() {
+            let obj_to_wld = parent_to_wld * Matrix4::from(node.transform().matrix());
+            if let Some(gltf_mesh) = node.mesh() {
+                // Nodes scaled to zero are valid glTF but invisible, and
+                // without an inverse they cannot be picked or collided with.
+                for &mesh_index in mesh_indices[gltf_mesh.index()].iter() {
+                    match Instance::new(mesh_index, obj_to_wld) {
+                        Some(instance) => instances.push(instance),
+                        None => log::warn!("Skipping node {} with a singular transform", node.index()),
+                    }
+                }
+            }
+            stack.extend(node.children().map(|child| (child, obj_to_wld)));
+        }
+    }
+
+    let materials = document
+        .materials()
+        .map(|material| {
+            let pbr = material.pbr_metallic_roughness();
+            let [r, g, b, a] = pbr.base_color_factor();
+            Material {
+                name: material.name().unwrap_or("").to_string(),
+                ambient: [0.0; 3],
+                diffuse: [r, g, b],
+                specular: [0.0; 3],
+                shininess: 0.0,
+                dissolve: a,
+                diffuse_texture: pbr.base_color_texture().and_then(|info| {
+                    match info.texture().source().source() {
+                        gltf::image::Source::Uri { uri, .. } => Some(uri.to_string()),
+                        gltf::image::Source::View { .. } => None,
+                    }
+                }),
+            }
+        })
+        .collect();
+
+    Ok(LoadedScene {
+        meshes,
+        materials,
+        instances,
+    })
+}
+
+const TREE_CACHE_MAGIC: [u8; 8] = *b"BVHCACHE";
+
+/// The tree cache is a directory holding one tree file per mesh, `0.bvh`,
+/// `1.bvh` and so on, next to an `index` with the source hash and the tree
+/// count. The trees are mapped with `MappedTree` when loading.
+fn cached_tree_path(cache_dir: &Path, mesh_index: usize) -> PathBuf {
+    cache_dir.join(format!("{}.bvh", mesh_index))
+}
+
+/// Maps the trees in the cache when it was written for a source file with
+/// the given hash. Trees that can't be opened are `None`.
+fn read_tree_cache(cache_dir: &Path, source_hash: u64) -> Result<Option<Vec<Option<MappedTree>>>, TreeFileError> {
+    let index = std::fs::read(cache_dir.join("index"))?;
+    if index.len() != 20 {
+        return Err(TreeFileError::Truncated);
+    }
+    if index[0..8] != TREE_CACHE_MAGIC {
+        return Err(TreeFileError::BadMagic);
+    }
+    if u64::from_le_bytes(index[8..16].try_into().unwrap()) != source_hash {
+        return Ok(None);
+    }
+    let tree_count = u32::from_le_bytes(index[16..20].try_into().unwrap());
+    let trees = (0..tree_count as usize)
+        .map(|mesh_index| MappedTree::open(&cached_tree_path(cache_dir, mesh_index)).ok())
+        .collect();
+    Ok(Some(trees))
+}
+
+/// Writes to a temporary file next to `path` and renames it over `path`, so
+/// readers see either the old or the new file and never a partial one.
+fn write_atomically<F>(path: &Path, write: F) -> Result<(), TreeFileError>
+where
+    F: FnOnce(&mut std::io::BufWriter<std::fs::File>) -> Result<(), TreeFileError>,
+{
+    use std::io::Write;
+
+    let temporary_path = path.with_extension("tmp");
+    let result = std::fs::File::create(&temporary_path)
+        .map_err(TreeFileError::from)
+        .and_then(|file| {
+            let mut writer = std::io::BufWriter::new(file);
+            write(&mut writer)?;
+            writer.flush()?;
+            writer.get_ref().sync_all()?;
+            Ok(())
+        })
+        .and_then(|()| Ok(std::fs::rename(&temporary_path, path)?));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temporary_path);
+    }
+    result
+}
+
+/// Writes the trees that were built rather than mapped from the cache, then
+/// the index. The old index is removed first so it never refers to trees
+/// of another source, even when writing fails halfway.
+fn write_tree_cache(cache_dir: &Path, source_hash: u64, meshes: &[Mesh]) -> Result<(), TreeFileError> {
+    use std::io::Write;
+
+    let tree_count: u32 = meshes
+        .len()
+        .try_into()
+        .map_err(|_| TreeFileError::Invalid("too many trees"))?;
+    std::fs::create_dir_all(cache_dir)?;
+    let index_path = cache_dir.join("index");
+    match std::fs::remove_file(&index_path) {
+        Err(error) if error.kind() != std::io::ErrorKind::NotFound => return Err(error.into()),
+        _ => {}
+    }
+    for (mesh_index, mesh) in meshes.iter().enumerate() {
+        if let MeshTree::Built(tree) = &mesh.bvh {
+            write_atomically(&cached_tree_path(cache_dir, mesh_index), |writer| {
+                tree.write_to(&mut *writer)
+            })?;
+        }
+    }
+    write_atomically(&index_path, |writer| {
+        writer.write_all(&TREE_CACHE_MAGIC)?;
+        writer.write_all(&source_hash.to_le_bytes())?;
+        writer.write_all(&tree_count.to_le_bytes())?;
+        Ok(())
+    })
+}
+
+/// FNV-1a hash of the file and, for glTF, of the external buffers it refers
+/// to. Images and OBJ materials are not hashed because they do not affect
+/// the trees.
+fn source_hash(path: &Path) -> Result<u64, SceneError> {
+    let bytes = std::fs::read(path)?;
+    let mut hasher = Fnv1a64::new();
+    hasher.update(&bytes);
+
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_ascii_lowercase());
+    if let Some("gltf") | Some("glb") = extension.as_deref() {
+        let gltf = gltf::Gltf::from_slice(&bytes)?;
+        let base = path.parent().unwrap_or_else(|| Path::new(""));
+        for buffer in gltf.buffers() {
+            if let gltf::buffer::Source::Uri(uri) = buffer.source() {
+                // Embedded data is part of the file already.
+                if !uri.starts_with("data:") {
+                    hasher.update(uri.as_bytes());
+                    hasher.update(&std::fs::read(base.join(uri))?);
+                }
+            }
+        }
+    }
+
+    Ok(hasher.value())
+}
+
+/// Like `load`, but maps the trees from the cache directory when it was
+/// written for source files with the same hash and they match the loaded
+/// meshes. Otherwise the trees are built and written to the cache. The
+/// cache is best-effort: failing to write it is logged and does not fail
+/// the load.
+pub fn load_cached(path: &Path, cache_dir: &Path) -> Result<LoadedScene, SceneError> {
+    let source_hash = source_hash(path)?;
+    let write_cache = |meshes: &[Mesh]| {
+        if let Err(error) = write_tree_cache(cache_dir, source_hash, meshes) {
+            log::warn!("Failed to write tree cache {:?}: {}", cache_dir, error);
+        }
+    };
+
+    if let Ok(Some(trees)) = read_tree_cache(cache_dir, source_hash) {
+        let tree_count = trees.len();
+        let mut trees = trees.into_iter();
+        let mut used = 0;
+        let mut stale = false;
+        let scene = load_with(path, &mut |vertices, triangles| {
+            used += 1;
+            match trees.next().flatten() {
+                Some(tree)
+                    if tree.view().triangles.len() == triangles.len()
+                        && tree
+                            .view()
+                            .triangles
+                            .iter()
+                            .flat_map(|triangle| triangle.iter())
+                            .all(|&index| (index as usize) < vertices.len()) =>
+                {
+                    MeshTree::Mapped(tree)
+                }
+                _ => {
+                    stale = true;
+                    build_tree(vertices, triangles)
+                }
+            }
+        })?;
+        if stale || used != tree_count {
+            write_cache(&scene.meshes);
+        }
+        return Ok(scene);
+    }
+
+    let scene = load(path)?;
+    write_cache(&scene.meshes);
+    Ok(scene)
+}
+
+#[derive(Debug)]
+pub struct MeshDescription {
+    pub vertex_offset: u32,
+    pub vertex_count: u32,
+    pub triangle_offset: u32,
+    pub triangle_count: u32,
+}
+
+/// The meshes concatenated into single vertex and triangle buffers, ready to
+/// be uploaded to the GPU.
+#[derive(Debug)]
+pub struct Scene {
+    pub pos_in_obj_buffer: Vec<[f32; 3]>,
+    pub triangle_buffer: Vec<[u32; 3]>,
+    pub mesh_descriptions: Vec<MeshDescription>,
+}
+
+impl Scene {
+    pub fn from_meshes(meshes: &[Mesh]) -> Result<Self, SceneError> {
+        let mut pos_in_obj_buffer = Vec::new();
+        let mut triangle_buffer = Vec::new();
+        let mut mesh_descriptions = Vec::with_capacity(meshes.len());
+
+        for mesh in meshes {
+            let to_u32 = |n: usize| -> Result<u32, SceneError> {
+                n.try_into().map_err(|_| SceneError::TooLarge)
+            };
+
+            mesh_descriptions.push(MeshDescription {
+                vertex_offset: to_u32(pos_in_obj_buffer.len())?,
+                vertex_count: to_u32(mesh.vertices.len())?,
+                triangle_offset: to_u32(triangle_buffer.len())?,
+                triangle_count: to_u32(mesh.triangles.len())?,
+            });
+
+            pos_in_obj_buffer.extend(mesh.vertices.iter().map(|&vertex| -> [f32; 3] { vertex.into() }));
+            triangle_buffer.extend(&mesh.triangles[..]);
+        }
+
+        Ok(Scene {
+            pos_in_obj_buffer,
+            triangle_buffer,
+            mesh_descriptions,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUAD: [f32; 12] = [0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0.0, 1.0, 0.0];
+
+    #[test]
+    fn mesh_validation() {
+        let mesh = Mesh::new("quad".to_string(), &QUAD, &[0, 1, 2, 0, 2, 3]).unwrap();
+        assert_eq!(4, mesh.vertices.len());
+        assert_eq!(2, mesh.triangles.len());
+
+        match Mesh::new("quad".to_string(), &QUAD, &[0, 1, 2, 0, 2, 4]) {
+            Err(SceneError::IndexOutOfRange { index: 4, .. }) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+        match Mesh::new("quad".to_string(), &QUAD, &[0, 1, 2, 0]) {
+            Err(SceneError::NonTriangularFaces { .. }) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+        match Mesh::new("quad".to_string(), &QUAD, &[]) {
+            Err(SceneError::EmptyMesh { .. }) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+        match Mesh::new("quad".to_string(), &QUAD[..11], &[0, 1, 2]) {
+            Err(SceneError::PositionCount { .. }) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+
+        match Mesh::new("quad".to_string(), &QUAD, &[0, 1, 2]).unwrap().with_texcoords(&[0.0; 6]) {
+            Err(SceneError::AttributeCount { .. }) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let scene = Scene::from_meshes(&[mesh]).unwrap();
+        assert_eq!(4, scene.pos_in_obj_buffer.len());
+        assert_eq!(2, scene.mesh_descriptions[0].triangle_count);
+    }
+
+    #[test]
+    fn surface_point_interpolates_attributes() {
+        let normals = [0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0.0, 1.0, 0.0, 0.0];
+        let texcoords = [0.0, 0.0, 1.0, 0.0, 1.0, 1.0, 0.0, 1.0];
+        let mesh = Mesh::new("quad".to_string(), &QUAD, &[0, 1, 2, 0, 2, 3])
+            .unwrap()
+            .with_normals(&normals)
+            .unwrap()
+            .with_texcoords(&texcoords)
+            .unwrap();
+
+        let ray = Ray {
+            origin: cgmath::Point3::new(0.75, 0.25, 1.0),
+            direction: cgmath::Vector3::new(0.0, 0.0, -1.0),
+        };
+        let hit = mesh.ray_hit(ray, f32::INFINITY).unwrap();
+        let point = mesh.surface_point(&hit);
+
+        assert!((hit.intersection.t - 1.0).abs() < 1e-6);
+        assert!((point.position - Vector3::from([0.75, 0.25, 0.0])).magnitude() < 1e-6);
+        let texcoord = point.texcoord.unwrap();
+        assert!((texcoord[0] - 0.75).abs() < 1e-6 && (texcoord[1] - 0.25).abs() < 1e-6);
+        // The normal tilts from +z at the first two vertices towards +x at the third.
+        assert!(point.normal.x > 0.0 && point.normal.z > 0.0);
+        assert!((point.normal.magnitude() - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn load_cached_reuses_trees() {
+        let dir = std::env::temp_dir().join(format!("bvh-cache-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("quad.stl");
+        let cache = dir.join("quad.stl.trees");
+        std::fs::write(
+            &source,
+            "solid quad\n\
+             facet normal 0 0 1 outer loop vertex 0 0 0 vertex 1 0 0 vertex 1 1 0 endloop endfacet\n\
+             facet normal 0 0 1 outer loop vertex 0 0 0 vertex 1 1 0 vertex 0 1 0 endloop endfacet\n\
+             endsolid quad\n",
+        )
+        .unwrap();
+
+        let built = load_cached(&source, &cache).unwrap();
+        assert!(matches!(built.meshes[0].bvh, MeshTree::Built(_)));
+        let tree_path = cache.join("0.bvh");
+        let tree_bytes = std::fs::read(&tree_path).unwrap();
+        let index_bytes = std::fs::read(cache.join("index")).unwrap();
+        let cached = load_cached(&source, &cache).unwrap();
+        assert!(matches!(cached.meshes[0].bvh, MeshTree::Mapped(_)));
+        assert_eq!(built.meshes[0].bvh.view().triangles, cached.meshes[0].bvh.view().triangles);
+        assert_eq!(tree_bytes, std::fs::read(&tree_path).unwrap());
+        assert_eq!(index_bytes, std::fs::read(cache.join("index")).unwrap());
+        drop(cached);
+
+        // A corrupt tree is rebuilt rather than trusted.
+        let mut corrupt = tree_bytes.clone();
+        let last = corrupt.len() - 1;
+        corrupt[last] ^= 0xff;
+        std::fs::write(&tree_path, &corrupt).unwrap();
+        let rebuilt = load_cached(&source, &cache).unwrap();
+        assert!(matches!(rebuilt.meshes[0].bvh, MeshTree::Built(_)));
+        assert_eq!(built.meshes[0].bvh.view().triangles, rebuilt.meshes[0].bvh.view().triangles);
+        assert_eq!(tree_bytes, std::fs::read(&tree_path).unwrap());
+
+        // The files are replaced by renaming temporary files.
+        assert!(!tree_path.with_extension("tmp").exists());
+        assert!(!cache.join("index.tmp").exists());
+
+        // Failing to write the cache does not fail the load.
+        assert!(load_cached(&source, &source.join("trees")).is_ok());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn gltf_shared_mesh_becomes_instances() {
+        let dir = std::env::temp_dir().join(format!("bvh-gltf-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut bin = Vec::new();
+        for &f in [0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0].iter() {
+            bin.extend_from_slice(&f.to_le_bytes());
+        }
+        for &i in [0u32, 1, 2].iter() {
+            bin.extend_from_slice(&i.to_le_bytes());
+        }
+        std::fs::write(dir.join("triangle.bin"), &bin).unwrap();
+
+        let json = r#"{
+            "asset": { "version": "2.0" },
+            "scene": 0,
+            "scenes": [{ "nodes": [0] }],
+            "nodes": [
+                { "children": [1, 2, 3], "translation": [10.0, 0.0, 0.0] },
+                { "mesh": 0 },
+                { "mesh": 0, "translation": [0.0, 5.0, 0.0] },
+                { "mesh": 0, "scale": [0.0, 0.0, 0.0] }
+            ],
+            "meshes": [{ "name": "triangle", "primitives": [{ "attributes": { "POSITION": 0 }, "indices": 1 }] }],
+            "accessors": [
+                { "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                  "min": [0.0, 0.0, 0.0], "max": [1.0, 1.0, 0.0] },
+                { "bufferView": 1, "componentType": 5125, "count": 3, "type": "SCALAR" }
+            ],
+            "bufferViews": [
+                { "buffer": 0, "byteOffset": 0, "byteLength": 36 },
+                { "buffer": 0, "byteOffset": 36, "byteLength": 12 }
+            ],
+            "buffers": [{ "uri": "triangle.bin", "byteLength": 48 }]
+        }"#;
+        std::fs::write(dir.join("scene.gltf"), json).unwrap();
+
+        let LoadedScene {
+            meshes,
+            materials,
+            instances,
+        } = load(&dir.join("scene.gltf")).unwrap();
+
+        // Editing the external buffer changes the hash the tree cache uses.
+        let hash = source_hash(&dir.join("scene.gltf")).unwrap();
+        bin[4..8].copy_from_slice(&2.0f32.to_le_bytes());
+        std::fs::write(dir.join("triangle.bin"), &bin).unwrap();
+        assert_ne!(hash, source_hash(&dir.join("scene.gltf")).unwrap());
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(1, meshes.len());
+        assert_eq!(3, meshes[0].vertices.len());
+        assert!(materials.is_empty());
+        assert_eq!(2, instances.len());
+
+        let mut translations: Vec<[f32; 3]> =
+            instances.iter().map(|instance| instance.obj_to_wld.w.truncate().into()).collect();
+        translations.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(vec![[10.0, 0.0, 0.0], [10.0, 5.0, 0.0]], translations);
+        assert!(instances.iter().all(|instance| instance.mesh_index == 0));
+        // The zero scaled node is skipped, the others carry their inverse.
+        for instance in instances.iter() {
+            assert_eq!(Matrix4::identity(), instance.obj_to_wld * instance.wld_to_obj);
+        }
+    }
+}
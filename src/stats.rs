@@ -0,0 +1,157 @@
+use std::collections::BTreeMap;
+
+use crate::aabb::*;
+use crate::bvh::*;
+
+/// Relative cost of visiting a branch in the surface area heuristic.
+pub const SAH_TRAVERSAL_COST: f32 = 1.0;
+/// Relative cost of testing a triangle in the surface area heuristic.
+pub const SAH_INTERSECTION_COST: f32 = 1.0;
+
+/// Shape and cost of the nodes reachable from the root.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Stats {
+    pub node_count: usize,
+    pub leaf_count: usize,
+    pub empty_leaf_count: usize,
+    pub max_depth: usize,
+    pub triangle_references: usize,
+    /// Number of nodes at every depth, the root is at depth 0.
+    pub depth_histogram: Vec<usize>,
+    /// Number of leaves per triangle count.
+    pub leaf_size_histogram: BTreeMap<u32, usize>,
+    /// Expected cost of a random ray hitting the root, with node areas
+    /// relative to the root's.
+    pub sah_cost: f32,
+    /// Summed surface area of the intersection of every pair of siblings.
+    pub child_overlap: f32,
+    /// Bytes used by all nodes and triangles, including unreachable ones.
+    pub memory_bytes: usize,
+}
+
+fn overlap(a: &AABB3, b: &AABB3) -> AABB3 {
+    let mut overlap = *a;
+    overlap.min.ew_max_assign(b.min);
+    overlap.max.ew_min_assign(b.max);
+    overlap
+}
+
+impl<'a> TreeView<'a> {
+    /// Assumes a valid tree, see `validate`.
+    pub fn stats(&self) -> Stats {
+        let mut stats = Stats {
+            memory_bytes: std::mem::size_of_val(self.nodes) + std::mem::size_of_val(self.triangles),
+            ..Stats::default()
+        };
+
+        if self.nodes.is_empty() {
+            return stats;
+        }
+
+        let root_area = self.nodes[0].aabb().surface_area();
+        let mut stack = vec![(0u32, 0usize)];
+
+        while let Some((node_index, depth)) = stack.pop() {
+            let node = &self.nodes[node_index as usize];
+            let aabb = node.aabb();
+            // An empty root or one collapsed to a line has no area to relate
+            // to, count every node as if it were hit.
+            let probability = if root_area > 0.0 {
+                aabb.surface_area() / root_area
+            } else {
+                1.0
+            };
+
+            stats.node_count += 1;
+            stats.max_depth = stats.max_depth.max(depth);
+            if stats.depth_histogram.len() <= depth {
+                stats.depth_histogram.resize(depth + 1, 0);
+            }
+            stats.depth_histogram[depth] += 1;
+
+            if node.is_branch() {
+                let left = &self.nodes[node.left_or_offset as usize];
+                let right = &self.nodes[node.left_or_offset as usize + 1];
+                stats.sah_cost += probability * SAH_TRAVERSAL_COST;
+                stats.child_overlap += overlap(&left.aabb(), &right.aabb()).surface_area();
+                stack.push((node.left_or_offset, depth + 1));
+                stack.push((node.left_or_offset + 1, depth + 1));
+            } else {
+                stats.leaf_count += 1;
+                if node.count == 0 {
+                    stats.empty_leaf_count += 1;
+                }
+                stats.triangle_references += node.count as usize;
+                *stats.leaf_size_histogram.entry(node.count).or_insert(0) += 1;
+                stats.sah_cost += probability * node.count as f32 * SAH_INTERSECTION_COST;
+            }
+        }
+
+        stats
+    }
+}
+
+impl Tree {
+    #[inline]
+    pub fn stats(&self) -> Stats {
+        self.view().stats()
+    }
+}
+
+impl std::fmt::Display for Stats {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "nodes: {}", self.node_count)?;
+        writeln!(f, "leaves: {} ({} empty)", self.leaf_count, self.empty_leaf_count)?;
+        writeln!(f, "triangle references: {}", self.triangle_references)?;
+        writeln!(f, "max depth: {}", self.max_depth)?;
+        writeln!(f, "sah cost: {:.3}", self.sah_cost)?;
+        writeln!(f, "child overlap: {:.3}", self.child_overlap)?;
+        writeln!(f, "memory: {} bytes", self.memory_bytes)?;
+        writeln!(f, "nodes per depth:")?;
+        for (depth, count) in self.depth_histogram.iter().enumerate() {
+            writeln!(f, "  {:>3}: {}", depth, count)?;
+        }
+        writeln!(f, "leaves per size:")?;
+        for (size, count) in self.leaf_size_histogram.iter() {
+            writeln!(f, "  {:>3}: {}", size, count)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vector::*;
+
+    #[test]
+    fn two_leaf_tree() {
+        // Flat boxes in the z = 0 plane.
+        let node = |x0: f32, x1: f32, left_or_offset, count| Node {
+            min: Vector3 { x: x0, y: 0.0, z: 0.0 },
+            left_or_offset,
+            max: Vector3 { x: x1, y: 1.0, z: 0.0 },
+            count,
+        };
+        let tree = Tree {
+            nodes: vec![
+                node(0.0, 4.0, 2, u32::MAX),
+                Node::unprocessed(),
+                node(0.0, 3.0, 0, 3),
+                node(2.0, 4.0, 3, 1),
+            ],
+            triangles: vec![[0, 0, 0]; 4],
+        };
+
+        let stats = tree.stats();
+        assert_eq!(3, stats.node_count);
+        assert_eq!(2, stats.leaf_count);
+        assert_eq!(vec![1, 2], stats.depth_histogram);
+        assert_eq!(Some(&1), stats.leaf_size_histogram.get(&3));
+        assert_eq!(4 * 32 + 4 * 12, stats.memory_bytes);
+        // Surface areas are 8, 6 and 4.
+        assert!((stats.sah_cost - (1.0 + 6.0 / 8.0 * 3.0 + 4.0 / 8.0 * 1.0)).abs() < 1e-6);
+        // The children overlap in a 1 by 1 square.
+        assert!((stats.child_overlap - 2.0).abs() < 1e-6);
+    }
+}
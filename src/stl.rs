@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::io::Read;
+
+use crate::bvh::*;
+use crate::vector::*;
+
+#[derive(Debug)]
+pub enum StlError {
+    Io(std::io::Error),
+    /// The binary triangle count does not match the file size.
+    Truncated,
+    /// An ASCII file could not be parsed.
+    Parse,
+    TooManyVertices,
+}
+
+impl std::fmt::Display for StlError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            StlError::Io(error) => write!(f, "{}", error),
+            StlError::Truncated => write!(f, "file is shorter than its triangle count requires"),
+            StlError::Parse => write!(f, "invalid ASCII STL"),
+            StlError::TooManyVertices => write!(f, "mesh does not fit in 32 bit indices"),
+        }
+    }
+}
+
+impl std::error::Error for StlError {}
+
+impl From<std::io::Error> for StlError {
+    fn from(error: std::io::Error) -> Self {
+        StlError::Io(error)
+    }
+}
+
+/// Merges vertices with identical coordinates. STL stores every triangle
+/// with its own copy of the corner positions, so without welding no two
+/// triangles would share a vertex.
+fn weld(corners: &[[f32; 3]]) -> Result<(Vec<Vector3<f32>>, Vec<Triangle>), StlError> {
+    let mut vertices = Vec::new();
+    let mut triangles = Vec::with_capacity(corners.len() / 3);
+    let mut indices: HashMap<[u32; 3], u32> = HashMap::new();
+
+    for triangle_corners in corners.chunks_exact(3) {
+        let mut triangle = [0; 3];
+        for (index, corner) in triangle.iter_mut().zip(triangle_corners.iter()) {
+            // Adding zero turns -0.0 into 0.0 so they weld together.
+            let corner = [corner[0] + 0.0, corner[1] + 0.0, corner[2] + 0.0];
+            let key = [corner[0].to_bits(), corner[1].to_bits(), corner[2].to_bits()];
+            let next: u32 = vertices.len().try_into().map_err(|_| StlError::TooManyVertices)?;
+            *index = *indices.entry(key).or_insert_with(|| {
+                vertices.push(Vector3::from(corner));
+                next
+            });
+        }
+        triangles.push(triangle);
+    }
+
+    Ok((vertices, triangles))
+}
+
+fn read_binary(bytes: &[u8]) -> Result<Vec<[f32; 3]>, StlError> {
+    if bytes.len() < 84 {
+        return Err(StlError::Truncated);
+    }
+    let count = u32::from_le_bytes(bytes[80..84].try_into().unwrap()) as usize;
+    let records = &bytes[84..];
+    // The size overflows on 32-bit targets for counts no file can hold.
+    match count.checked_mul(50) {
+        Some(size) if size <= records.len() => {}
+        _ => return Err(StlError::Truncated),
+    }
+
+    let f32_at = |record: &[u8], offset: usize| {
+        f32::from_le_bytes(record[offset..offset + 4].try_into().unwrap())
+    };
+
+    let mut corners = Vec::with_capacity(count * 3);
+    for record in records.chunks_exact(50).take(count) {
+        // Skip the 12 byte facet normal, read 3 corners and ignore the 2 byte attribute.
+        for corner in 0..3 {
+            let offset = 12 + corner * 12;
+            corners.push([
+                f32_at(record, offset),
+                f32_at(record, offset + 4),
+                f32_at(record, offset + 8),
+            ]);
+        }
+    }
+    Ok(corners)
+}
+
+fn read_ascii(text: &str) -> Result<Vec<[f32; 3]>, StlError> {
+    let mut corners = Vec::new();
+    let mut words = text.split_ascii_whitespace();
+    while let Some(word) = words.next() {
+        if word == "vertex" {
+            let mut corner = [0.0; 3];
+            for coordinate in corner.iter_mut() {
+                *coordinate = words
+                    .next()
+                    .and_then(|word| word.parse().ok())
+                    .ok_or(StlError::Parse)?;
+            }
+            corners.push(corner);
+        }
+    }
+    if corners.len() % 3 != 0 {
+        return Err(StlError::Parse);
+    }
+    Ok(corners)
+}
+
+/// Reads an ASCII or binary STL file and welds identical vertices.
+pub fn read_stl<R: Read>(mut reader: R) -> Result<(Vec<Vector3<f32>>, Vec<Triangle>), StlError> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+
+    // Binary files may also start with "solid", so trust the size first.
+    let binary_size = bytes
+        .get(80..84)
+        .and_then(|count| (u32::from_le_bytes(count.try_into().unwrap()) as usize).checked_mul(50))
+        .and_then(|size| size.checked_add(84));
+    let corners = if binary_size == Some(bytes.len()) || !bytes.starts_with(b"solid") {
+        read_binary(&bytes)?
+    } else {
+        read_ascii(std::str::from_utf8(&bytes).map_err(|_| StlError::Parse)?)?
+    };
+
+    weld(&corners)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_and_weld() {
+        let ascii = "solid quad
+  facet normal 0 0 1
+    outer loop
+      vertex 0 0 0
+      vertex 1 0 0
+      vertex 1 1 0
+    endloop
+  endfacet
+  facet normal 0 0 1
+    outer loop
+      vertex -0 0 0
+      vertex 1 1 0
+      vertex 0 1 0
+    endloop
+  endfacet
+endsolid quad
+";
+        let (vertices, triangles) = read_stl(ascii.as_bytes()).unwrap();
+        assert_eq!(4, vertices.len());
+        assert_eq!(vec![[0, 1, 2], [0, 2, 3]], triangles);
+
+        let mut binary = b"solid but actually binary".to_vec();
+        binary.resize(80, 0);
+        binary.extend_from_slice(&2u32.to_le_bytes());
+        let quad = [
+            [0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0.0, 1.0, 0.0],
+        ];
+        for corners in quad.iter() {
+            binary.extend_from_slice(&[0; 12]);
+            for &f in corners.iter() {
+                binary.extend_from_slice(&f.to_le_bytes());
+            }
+            binary.extend_from_slice(&[0; 2]);
+        }
+        let (vertices, triangles) = read_stl(&binary[..]).unwrap();
+        assert_eq!(4, vertices.len());
+        assert_eq!(vec![[0, 1, 2], [0, 2, 3]], triangles);
+
+        assert!(read_stl(&binary[..binary.len() - 10]).is_err());
+
+        // Without the misleading "solid" the size is checked as binary. A
+        // count far beyond the file size is truncated, not an overflow.
+        binary[..5].copy_from_slice(b"quad ");
+        assert!(matches!(read_stl(&binary[..binary.len() - 10]), Err(StlError::Truncated)));
+        binary[80..84].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(read_stl(&binary[..]), Err(StlError::Truncated)));
+    }
+}
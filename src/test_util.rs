@@ -0,0 +1,37 @@
+//! Fixtures shared by the unit tests.
+
+use crate::bvh::*;
+use crate::vector::*;
+
+pub fn v(x: f32, y: f32, z: f32) -> Vector3<f32> {
+    Vector3 { x, y, z }
+}
+
+/// Unit cube from the origin to (1, 1, 1) with outward facing triangles.
+pub fn unit_cube() -> (Vec<Vector3<f32>>, Vec<Triangle>) {
+    let vertices = vec![
+        v(0.0, 0.0, 0.0),
+        v(1.0, 0.0, 0.0),
+        v(1.0, 1.0, 0.0),
+        v(0.0, 1.0, 0.0),
+        v(0.0, 0.0, 1.0),
+        v(1.0, 0.0, 1.0),
+        v(1.0, 1.0, 1.0),
+        v(0.0, 1.0, 1.0),
+    ];
+    let triangles = vec![
+        [0, 2, 1],
+        [0, 3, 2],
+        [4, 5, 6],
+        [4, 6, 7],
+        [0, 1, 5],
+        [0, 5, 4],
+        [3, 7, 6],
+        [3, 6, 2],
+        [0, 4, 7],
+        [0, 7, 3],
+        [1, 2, 6],
+        [1, 6, 5],
+    ];
+    (vertices, triangles)
+}
@@ -0,0 +1,394 @@
+//! A versioned binary format for built trees.
+//!
+//! All values are little-endian. The file starts with a 32 byte header:
+//!
+//! | offset | type     | content                                   |
+//! |--------|----------|-------------------------------------------|
+//! | 0      | [u8; 8]  | magic `b"BVHTREE\0"`                      |
+//! | 8      | u32      | format version                            |
+//! | 12     | u32      | node count                                |
+//! | 16     | u32      | triangle count                            |
+//! | 20     | u32      | reserved, zero                            |
+//! | 24     | u64      | FNV-1a checksum of everything that follows |
+//!
+//! The nodes follow as 32 byte records laid out like `Node`: min x, y, z as
+//! f32, `left_or_offset` as u32, max x, y, z as f32 and `count` as u32.
+//! Then come the triangles as three u32 vertex indices each.
+//!
+//! Because the payload starts at a multiple of 4 bytes, a memory mapped
+//! file can be traversed in place on little-endian targets, see
+//! `TreeView::from_bytes` and `MappedTree`.
+
+use std::convert::TryInto;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use crate::bvh::*;
+use crate::hash::*;
+use crate::pod::*;
+use crate::vector::*;
+
+pub const TREE_FILE_MAGIC: [u8; 8] = *b"BVHTREE\0";
+pub const TREE_FILE_VERSION: u32 = 1;
+
+const HEADER_SIZE: usize = 32;
+const NODE_SIZE: usize = 32;
+const TRIANGLE_SIZE: usize = 12;
+
+const _: () = assert!(std::mem::size_of::<Node>() == NODE_SIZE);
+const _: () = assert!(std::mem::size_of::<Triangle>() == TRIANGLE_SIZE);
+
+#[derive(Debug)]
+pub enum TreeFileError {
+    Io(std::io::Error),
+    BadMagic,
+    UnsupportedVersion(u32),
+    /// The file ended before all nodes and triangles were read.
+    Truncated,
+    ChecksumMismatch,
+    /// The data is intact but does not describe a valid tree.
+    Invalid(&'static str),
+    /// The bytes to view in place are not aligned for `Node`.
+    Misaligned,
+    /// Viewing in place requires a little-endian target.
+    UnsupportedTarget,
+}
+
+impl std::fmt::Display for TreeFileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            TreeFileError::Io(error) => write!(f, "{}", error),
+            TreeFileError::BadMagic => write!(f, "not a tree file"),
+            TreeFileError::UnsupportedVersion(version) => {
+                write!(f, "unsupported tree file version {}", version)
+            }
+            TreeFileError::Truncated => write!(f, "tree file is truncated"),
+            TreeFileError::ChecksumMismatch => write!(f, "tree file checksum mismatch"),
+            TreeFileError::Invalid(reason) => write!(f, "invalid tree: {}", reason),
+            TreeFileError::Misaligned => write!(f, "tree data is not aligned"),
+            TreeFileError::UnsupportedTarget => {
+                write!(f, "tree files can only be viewed in place on little-endian targets")
+            }
+        }
+    }
+}
+
+impl std::error::Error for TreeFileError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TreeFileError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for TreeFileError {
+    fn from(error: std::io::Error) -> Self {
+        TreeFileError::Io(error)
+    }
+}
+
+#[inline]
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+#[inline]
+fn f32_at(bytes: &[u8], offset: usize) -> f32 {
+    f32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+struct Header {
+    node_count: usize,
+    triangle_count: usize,
+    checksum: u64,
+}
+
+impl Header {
+    fn parse(bytes: &[u8; HEADER_SIZE]) -> Result<Self, TreeFileError> {
+        if bytes[0..8] != TREE_FILE_MAGIC {
+            return Err(TreeFileError::BadMagic);
+        }
+        let version = u32_at(bytes, 8);
+        if version != TREE_FILE_VERSION {
+            return Err(TreeFileError::UnsupportedVersion(version));
+        }
+        Ok(Header {
+            node_count: u32_at(bytes, 12) as usize,
+            triangle_count: u32_at(bytes, 16) as usize,
+            checksum: u64::from_le_bytes(bytes[24..32].try_into().unwrap()),
+        })
+    }
+
+    fn payload_size(&self) -> usize {
+        self.node_count * NODE_SIZE + self.triangle_count * TRIANGLE_SIZE
+    }
+}
+
+/// Checks that every branch refers to two later nodes and every leaf to a
+/// range of the stored triangles, so traversal can not loop or index out of
+/// bounds.
+fn check_structure(nodes: &[Node], triangle_count: usize) -> Result<(), TreeFileError> {
+    if nodes.is_empty() {
+        return Err(TreeFileError::Invalid("missing root node"));
+    }
+    for (node_index, node) in nodes.iter().enumerate() {
+        if node.is_branch() {
+            let left = node.left_or_offset as usize;
+            if left <= node_index || left + 1 >= nodes.len() {
+                return Err(TreeFileError::Invalid("branch child out of range"));
+            }
+        } else {
+            let end = node.left_or_offset as u64 + node.count as u64;
+            if end > triangle_count as u64 {
+                return Err(TreeFileError::Invalid("leaf triangles out of range"));
+            }
+        }
+    }
+    Ok(())
+}
+
+impl From<CastError> for TreeFileError {
+    fn from(error: CastError) -> Self {
+        match error {
+            CastError::Misaligned => TreeFileError::Misaligned,
+            CastError::Size => TreeFileError::Truncated,
+        }
+    }
+}
+
+/// Splits a tree file into its header and the nodes and triangles viewed in
+/// place. Only checks the sizes and alignment.
+fn split_file(bytes: &[u8]) -> Result<(Header, TreeView<'_>), TreeFileError> {
+    if cfg!(target_endian = "big") {
+        return Err(TreeFileError::UnsupportedTarget);
+    }
+    if bytes.len() < HEADER_SIZE {
+        return Err(TreeFileError::Truncated);
+    }
+    let header = Header::parse(bytes[0..HEADER_SIZE].try_into().unwrap())?;
+    let payload = &bytes[HEADER_SIZE..];
+    if payload.len() < header.payload_size() {
+        return Err(TreeFileError::Truncated);
+    }
+    if payload.len() > header.payload_size() {
+        return Err(TreeFileError::Invalid("trailing bytes"));
+    }
+    let (node_bytes, triangle_bytes) = payload.split_at(header.node_count * NODE_SIZE);
+    let view = TreeView {
+        nodes: cast_slice(node_bytes)?,
+        triangles: cast_slice(triangle_bytes)?,
+    };
+    Ok((header, view))
+}
+
+impl<'a> TreeView<'a> {
+    /// Views a complete tree file in place, verifying the checksum and
+    /// structure up front like `Tree::read_from`. The bytes must be aligned
+    /// to 4 bytes, which memory maps and most allocations are.
+    pub fn from_bytes(bytes: &'a [u8]) -> Result<Self, TreeFileError> {
+        let (header, view) = split_file(bytes)?;
+        if fnv1a64(&bytes[HEADER_SIZE..]) != header.checksum {
+            return Err(TreeFileError::ChecksumMismatch);
+        }
+        check_structure(view.nodes, view.triangles.len())?;
+        Ok(view)
+    }
+}
+
+/// A tree file mapped into memory. The pages are shared between processes
+/// mapping the same file, so large trees are only loaded once.
+#[derive(Debug)]
+pub struct MappedTree {
+    map: memmap::Mmap,
+}
+
+impl MappedTree {
+    pub fn open(path: &Path) -> Result<Self, TreeFileError> {
+        let file = std::fs::File::open(path)?;
+        // The file must not be truncated or modified while it is mapped,
+        // tree files are only ever replaced as a whole.
+        let map = unsafe { memmap::Mmap::map(&file)? };
+        TreeView::from_bytes(&map)?;
+        Ok(MappedTree { map })
+    }
+
+    #[inline]
+    pub fn view(&self) -> TreeView<'_> {
+        // Verified in `open`.
+        split_file(&self.map).unwrap().1
+    }
+}
+
+impl Tree {
+    pub fn write_to<W: Write>(&self, mut writer: W) -> Result<(), TreeFileError> {
+        let node_count: u32 = self
+            .nodes
+            .len()
+            .try_into()
+            .map_err(|_| TreeFileError::Invalid("too many nodes"))?;
+        let triangle_count: u32 = self
+            .triangles
+            .len()
+            .try_into()
+            .map_err(|_| TreeFileError::Invalid("too many triangles"))?;
+
+        let mut payload = Vec::with_capacity(
+            self.nodes.len() * NODE_SIZE + self.triangles.len() * TRIANGLE_SIZE,
+        );
+        for node in self.nodes.iter() {
+            for &value in [node.min.x, node.min.y, node.min.z].iter() {
+                payload.extend_from_slice(&value.to_le_bytes());
+            }
+            payload.extend_from_slice(&node.left_or_offset.to_le_bytes());
+            for &value in [node.max.x, node.max.y, node.max.z].iter() {
+                payload.extend_from_slice(&value.to_le_bytes());
+            }
+            payload.extend_from_slice(&node.count.to_le_bytes());
+        }
+        for triangle in self.triangles.iter() {
+            for &index in triangle.iter() {
+                payload.extend_from_slice(&index.to_le_bytes());
+            }
+        }
+
+        let mut header = Vec::with_capacity(HEADER_SIZE);
+        header.extend_from_slice(&TREE_FILE_MAGIC);
+        header.extend_from_slice(&TREE_FILE_VERSION.to_le_bytes());
+        header.extend_from_slice(&node_count.to_le_bytes());
+        header.extend_from_slice(&triangle_count.to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes());
+        header.extend_from_slice(&fnv1a64(&payload).to_le_bytes());
+
+        writer.write_all(&header)?;
+        writer.write_all(&payload)?;
+        Ok(())
+    }
+
+    /// Reads a tree written by `write_to`, verifying the checksum and the
+    /// structure.
+    pub fn read_from<R: Read>(mut reader: R) -> Result<Self, TreeFileError> {
+        let mut header_bytes = [0u8; HEADER_SIZE];
+        reader.read_exact(&mut header_bytes).map_err(|error| match error.kind() {
+            std::io::ErrorKind::UnexpectedEof => TreeFileError::Truncated,
+            _ => TreeFileError::Io(error),
+        })?;
+        let header = Header::parse(&header_bytes)?;
+
+        // Read through `take` so a corrupt count can't make us allocate
+        // more than the file actually contains.
+        let payload_size = header.payload_size() as u64;
+        let mut payload = Vec::new();
+        reader.take(payload_size).read_to_end(&mut payload)?;
+        if (payload.len() as u64) < payload_size {
+            return Err(TreeFileError::Truncated);
+        }
+        if fnv1a64(&payload) != header.checksum {
+            return Err(TreeFileError::ChecksumMismatch);
+        }
+
+        let (node_bytes, triangle_bytes) = payload.split_at(header.node_count * NODE_SIZE);
+        let nodes: Vec<Node> = node_bytes
+            .chunks_exact(NODE_SIZE)
+            .map(|b| Node {
+                min: Vector3::from([f32_at(b, 0), f32_at(b, 4), f32_at(b, 8)]),
+                left_or_offset: u32_at(b, 12),
+                max: Vector3::from([f32_at(b, 16), f32_at(b, 20), f32_at(b, 24)]),
+                count: u32_at(b, 28),
+            })
+            .collect();
+        let triangles: Vec<Triangle> = triangle_bytes
+            .chunks_exact(TRIANGLE_SIZE)
+            .map(|b| [u32_at(b, 0), u32_at(b, 4), u32_at(b, 8)])
+            .collect();
+
+        check_structure(&nodes, triangles.len())?;
+
+        Ok(Tree { nodes, triangles })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grid_tree() -> Tree {
+        let mut vertices = Vec::new();
+        let mut triangles = Vec::new();
+        for i in 0..40u32 {
+            let x = i as f32;
+            vertices.push(Vector3::from([x, 0.0, 0.0]));
+            vertices.push(Vector3::from([x + 1.0, 0.0, 0.0]));
+            vertices.push(Vector3::from([x, 1.0, x * 0.5]));
+            triangles.push([i * 3, i * 3 + 1, i * 3 + 2]);
+        }
+        Tree::new(&vertices, &triangles)
+    }
+
+    #[test]
+    fn round_trip_and_corruption() {
+        let tree = grid_tree();
+        let mut bytes = Vec::new();
+        tree.write_to(&mut bytes).unwrap();
+        assert_eq!(HEADER_SIZE + tree.nodes.len() * 32 + tree.triangles.len() * 12, bytes.len());
+
+        let read = Tree::read_from(&bytes[..]).unwrap();
+        assert_eq!(tree.triangles, read.triangles);
+        assert_eq!(tree.nodes.len(), read.nodes.len());
+        for (a, b) in tree.nodes.iter().zip(read.nodes.iter()) {
+            assert_eq!(a.left_or_offset, b.left_or_offset);
+            assert_eq!(a.count, b.count);
+            assert_eq!(Into::<[f32; 3]>::into(a.min), Into::<[f32; 3]>::into(b.min));
+        }
+
+        let mut corrupt = bytes.clone();
+        let last = corrupt.len() - 1;
+        corrupt[last] ^= 1;
+        match Tree::read_from(&corrupt[..]) {
+            Err(TreeFileError::ChecksumMismatch) => {}
+            other => panic!("unexpected {:?}", other.map(|_| ())),
+        }
+
+        match Tree::read_from(&bytes[..bytes.len() - 1]) {
+            Err(TreeFileError::Truncated) => {}
+            other => panic!("unexpected {:?}", other.map(|_| ())),
+        }
+
+        let mut future = bytes.clone();
+        future[8] = 2;
+        match Tree::read_from(&future[..]) {
+            Err(TreeFileError::UnsupportedVersion(2)) => {}
+            other => panic!("unexpected {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn mapped_view_matches_tree() {
+        let tree = grid_tree();
+        let mut bytes = Vec::new();
+        tree.write_to(&mut bytes).unwrap();
+
+        let path = std::env::temp_dir().join(format!("bvh-mapped-{}.bvh", std::process::id()));
+        std::fs::write(&path, &bytes).unwrap();
+        let mapped = MappedTree::open(&path).unwrap();
+        let view = mapped.view();
+        assert_eq!(&tree.triangles[..], view.triangles);
+        assert_eq!(tree.nodes.len(), view.nodes.len());
+        for (a, b) in tree.nodes.iter().zip(view.nodes.iter()) {
+            assert_eq!(a.left_or_offset, b.left_or_offset);
+            assert_eq!(a.count, b.count);
+            assert_eq!(Into::<[f32; 3]>::into(a.max), Into::<[f32; 3]>::into(b.max));
+        }
+        drop(mapped);
+        std::fs::remove_file(&path).unwrap();
+
+        // Place the file at an odd address.
+        let mut buffer = vec![0u8; bytes.len() + 4];
+        let start = (0..4).find(|i| (buffer.as_ptr() as usize + i) % 4 == 1).unwrap();
+        buffer[start..start + bytes.len()].copy_from_slice(&bytes);
+        match TreeView::from_bytes(&buffer[start..start + bytes.len()]) {
+            Err(TreeFileError::Misaligned) => {}
+            other => panic!("unexpected {:?}", other.map(|_| ())),
+        }
+    }
+}
@@ -0,0 +1,344 @@
+use crate::aabb::*;
+use crate::bvh::*;
+use crate::stats::*;
+use crate::vector::*;
+
+#[derive(Debug, Clone)]
+pub struct ValidationOptions {
+    /// How many references beyond one per triangle slot are accepted, that
+    /// is how much leaf ranges may overlap. Builders that split triangles
+    /// across leaves reference some slots more than once.
+    pub duplicate_budget: usize,
+    /// A node that is allowed to be unreachable. `Tree::new` leaves node 1
+    /// unused so that siblings are adjacent and the root has none.
+    pub padding_node: Option<u32>,
+}
+
+impl Default for ValidationOptions {
+    /// Accepts trees built by `Tree::new`.
+    fn default() -> Self {
+        ValidationOptions {
+            duplicate_budget: 0,
+            padding_node: Some(1),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum ValidationError {
+    MissingRoot,
+    /// A branch refers to children past the end of the nodes or to the root.
+    ChildOutOfRange { node_index: u32 },
+    /// The node is reached more than once, through a cycle or because two
+    /// branches share children.
+    Cycle { node_index: u32 },
+    /// A node that is not reachable from the root, other than the padding
+    /// node in the options.
+    UnreachableNode { node_index: u32 },
+    /// The bounds of the node are not contained in those of its parent.
+    ChildNotContained { node_index: u32 },
+    /// The leaf's triangle range extends past the end of the triangles.
+    /// Because `count == u32::MAX` marks branches, this also catches nodes
+    /// meant to be branches with a corrupt count.
+    LeafOutOfRange { node_index: u32 },
+    VertexOutOfRange { triangle_index: u32 },
+    /// A vertex of the triangle lies outside the bounds of its leaf.
+    TriangleNotContained { triangle_index: u32 },
+    /// The triangle is not referenced by any leaf.
+    UnreferencedTriangle { triangle_index: u32 },
+    /// More triangle slots are referenced multiple times, by overlapping
+    /// leaves, than the budget allows.
+    DuplicateTriangles { count: usize },
+}
+
+impl std::fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ValidationError::MissingRoot => write!(f, "tree has no root node"),
+            ValidationError::ChildOutOfRange { node_index } => {
+                write!(f, "children of node {} are out of range", node_index)
+            }
+            ValidationError::Cycle { node_index } => write!(f, "node {} is reached more than once", node_index),
+            ValidationError::UnreachableNode { node_index } => write!(f, "node {} is unreachable", node_index),
+            ValidationError::ChildNotContained { node_index } => {
+                write!(f, "bounds of node {} exceed those of its parent", node_index)
+            }
+            ValidationError::LeafOutOfRange { node_index } => {
+                write!(f, "triangles of leaf {} are out of range", node_index)
+            }
+            ValidationError::VertexOutOfRange { triangle_index } => {
+                write!(f, "triangle {} refers to a missing vertex", triangle_index)
+            }
+            ValidationError::TriangleNotContained { triangle_index } => {
+                write!(f, "triangle {} exceeds the bounds of its leaf", triangle_index)
+            }
+            ValidationError::UnreferencedTriangle { triangle_index } => {
+                write!(f, "triangle {} is not in any leaf", triangle_index)
+            }
+            ValidationError::DuplicateTriangles { count } => {
+                write!(f, "{} duplicate triangle references exceed the budget", count)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+#[inline]
+fn contains_aabb(outer: &AABB3, inner: &AABB3) -> bool {
+    outer.min.x <= inner.min.x
+        && outer.min.y <= inner.min.y
+        && outer.min.z <= inner.min.z
+        && inner.max.x <= outer.max.x
+        && inner.max.y <= outer.max.y
+        && inner.max.z <= outer.max.z
+}
+
+/// Unlike `AABB3::contains_point` this includes the maximum.
+#[inline]
+fn contains_point(aabb: &AABB3, p: Vector3<f32>) -> bool {
+    aabb.min.x <= p.x
+        && aabb.min.y <= p.y
+        && aabb.min.z <= p.z
+        && p.x <= aabb.max.x
+        && p.y <= aabb.max.y
+        && p.z <= aabb.max.z
+}
+
+impl<'a> TreeView<'a> {
+    /// Validates with the default options, which expect the layout of
+    /// `Tree::new` with node 1 unused.
+    #[inline]
+    pub fn validate(&self, vertices: &[Vector3<f32>]) -> Result<Stats, ValidationError> {
+        self.validate_with(vertices, &ValidationOptions::default())
+    }
+
+    pub fn validate_with(
+        &self,
+        vertices: &[Vector3<f32>],
+        options: &ValidationOptions,
+    ) -> Result<Stats, ValidationError> {
+        if self.nodes.is_empty() {
+            return Err(ValidationError::MissingRoot);
+        }
+
+        let mut visited = vec![false; self.nodes.len()];
+        let mut references = vec![0u32; self.triangles.len()];
+        let mut stack = vec![0u32];
+
+        while let Some(node_index) = stack.pop() {
+            let visited = &mut visited[node_index as usize];
+            if *visited {
+                return Err(ValidationError::Cycle { node_index });
+            }
+            *visited = true;
+
+            let node = &self.nodes[node_index as usize];
+
+            if node.is_branch() {
+                let left = node.left_or_offset;
+                if left == 0 || left as usize + 1 >= self.nodes.len() {
+                    return Err(ValidationError::ChildOutOfRange { node_index });
+                }
+                for child_index in left..left + 2 {
+                    if !contains_aabb(&node.aabb(), &self.nodes[child_index as usize].aabb()) {
+                        return Err(ValidationError::ChildNotContained {
+                            node_index: child_index,
+                        });
+                    }
+                    stack.push(child_index);
+                }
+            } else {
+                let end = node.left_or_offset as u64 + node.count as u64;
+                if end > self.triangles.len() as u64 {
+                    return Err(ValidationError::LeafOutOfRange { node_index });
+                }
+
+                let aabb = node.aabb();
+                for triangle_index in node.left_or_offset..node.left_or_offset + node.count {
+                    references[triangle_index as usize] += 1;
+                    let triangle = self.triangles[triangle_index as usize];
+                    if triangle.iter().any(|&i| i as usize >= vertices.len()) {
+                        return Err(ValidationError::VertexOutOfRange { triangle_index });
+                    }
+                    // Non-finite vertices can not be bounded, they are only
+                    // kept when the builder is asked to.
+                    let contained = triangle.iter().map(|&i| vertices[i as usize]).all(|p| {
+                        let finite = p.x.is_finite() && p.y.is_finite() && p.z.is_finite();
+                        !finite || contains_point(&aabb, p)
+                    });
+                    if !contained {
+                        return Err(ValidationError::TriangleNotContained { triangle_index });
+                    }
+                }
+            }
+        }
+
+        let padding_node = options.padding_node.map(|index| index as usize);
+        if let Some(node_index) = (0..self.nodes.len())
+            .find(|&i| !visited[i] && Some(i) != padding_node)
+        {
+            return Err(ValidationError::UnreachableNode {
+                node_index: node_index as u32,
+            });
+        }
+
+        if let Some(triangle_index) = references.iter().position(|&count| count == 0) {
+            return Err(ValidationError::UnreferencedTriangle {
+                triangle_index: triangle_index as u32,
+            });
+        }
+
+        // Compare slots, not values: meshes may well contain the same face
+        // twice.
+        let reference_count: usize = references.iter().map(|&count| count as usize).sum();
+        let duplicates = reference_count - references.len();
+        if duplicates > options.duplicate_budget {
+            return Err(ValidationError::DuplicateTriangles { count: duplicates });
+        }
+
+        Ok(self.stats())
+    }
+}
+
+impl Tree {
+    /// Checks the invariants every tree should satisfy and returns its
+    /// statistics. Expects node 1 to be unused like `Tree::new` does, use
+    /// `validate_with` for other layouts.
+    #[inline]
+    pub fn validate(&self, vertices: &[Vector3<f32>]) -> Result<Stats, ValidationError> {
+        self.view().validate(vertices)
+    }
+
+    #[inline]
+    pub fn validate_with(
+        &self,
+        vertices: &[Vector3<f32>],
+        options: &ValidationOptions,
+    ) -> Result<Stats, ValidationError> {
+        self.view().validate_with(vertices, options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strip(count: u32) -> (Vec<Vector3<f32>>, Vec<Triangle>) {
+        let mut vertices = Vec::new();
+        let mut triangles = Vec::new();
+        for i in 0..count {
+            let x = i as f32;
+            vertices.push(Vector3 { x, y: 0.0, z: 0.0 });
+            vertices.push(Vector3 { x: x + 1.0, y: 0.0, z: 0.0 });
+            vertices.push(Vector3 { x, y: 1.0, z: (x * 0.7).sin() });
+            triangles.push([i * 3, i * 3 + 1, i * 3 + 2]);
+        }
+        (vertices, triangles)
+    }
+
+    #[test]
+    fn built_trees_validate_and_corruption_is_caught() {
+        let (mut vertices, mut triangles) = strip(200);
+        // Meshes may contain the same face more than once.
+        triangles.push(triangles[7]);
+        let tree = Tree::new(&vertices, &triangles);
+        let stats = tree.validate(&vertices).unwrap();
+        assert_eq!(201, stats.triangle_references);
+        assert_eq!(tree.nodes.len() - 1, stats.node_count);
+        assert_eq!(stats.node_count, 2 * stats.leaf_count - 1);
+
+        let copy = || Tree {
+            nodes: tree.nodes.clone(),
+            triangles: tree.triangles.clone(),
+        };
+
+        // The last branch has two leaves as children.
+        let branch_index = tree.nodes.iter().rposition(|node| node.is_branch()).unwrap() as u32;
+        let left = tree.nodes[branch_index as usize].left_or_offset;
+        let right = left + 1;
+        let left_leaf = tree.nodes[left as usize];
+        let right_leaf = tree.nodes[right as usize];
+
+        let mut cycle = copy();
+        cycle.nodes[left as usize] = cycle.nodes[branch_index as usize];
+        assert!(matches!(cycle.validate(&vertices), Err(ValidationError::Cycle { .. })));
+
+        let mut grown = copy();
+        grown.nodes[left as usize].max.z += 10.0;
+        assert_eq!(
+            Err(ValidationError::ChildNotContained { node_index: left }),
+            grown.validate(&vertices)
+        );
+
+        let mut out_of_range = copy();
+        out_of_range.nodes[branch_index as usize].left_or_offset = tree.nodes.len() as u32 - 1;
+        assert_eq!(
+            Err(ValidationError::ChildOutOfRange { node_index: branch_index }),
+            out_of_range.validate(&vertices)
+        );
+
+        let mut corrupt_count = copy();
+        corrupt_count.nodes[branch_index as usize].count = u32::MAX - 1;
+        assert_eq!(
+            Err(ValidationError::LeafOutOfRange { node_index: branch_index }),
+            corrupt_count.validate(&vertices)
+        );
+
+        let mut extra = copy();
+        extra.nodes.push(Node::unprocessed());
+        assert_eq!(
+            Err(ValidationError::UnreachableNode {
+                node_index: tree.nodes.len() as u32
+            }),
+            extra.validate(&vertices)
+        );
+        let unpadded = ValidationOptions {
+            padding_node: None,
+            ..ValidationOptions::default()
+        };
+        assert_eq!(
+            Err(ValidationError::UnreachableNode { node_index: 1 }),
+            tree.validate_with(&vertices, &unpadded)
+        );
+
+        let mut shrunk = copy();
+        shrunk.nodes[right as usize].count -= 1;
+        assert_eq!(
+            Err(ValidationError::UnreferencedTriangle {
+                triangle_index: right_leaf.left_or_offset + right_leaf.count - 1
+            }),
+            shrunk.validate(&vertices)
+        );
+
+        // Let the left leaf overlap the first slot of its sibling.
+        assert_eq!(left_leaf.left_or_offset + left_leaf.count, right_leaf.left_or_offset);
+        let mut overlapping = copy();
+        overlapping.nodes[left as usize].count += 1;
+        overlapping.nodes[left as usize].min = tree.nodes[branch_index as usize].min;
+        overlapping.nodes[left as usize].max = tree.nodes[branch_index as usize].max;
+        assert_eq!(
+            Err(ValidationError::DuplicateTriangles { count: 1 }),
+            overlapping.validate(&vertices)
+        );
+        let budget = ValidationOptions {
+            duplicate_budget: 1,
+            ..ValidationOptions::default()
+        };
+        assert!(overlapping.validate_with(&vertices, &budget).is_ok());
+
+        assert!(matches!(
+            tree.validate(&vertices[..1]),
+            Err(ValidationError::VertexOutOfRange { .. })
+        ));
+
+        let moved_triangle = right_leaf.left_or_offset;
+        vertices[tree.triangles[moved_triangle as usize][0] as usize].x += 1000.0;
+        assert_eq!(
+            Err(ValidationError::TriangleNotContained {
+                triangle_index: moved_triangle
+            }),
+            tree.validate(&vertices)
+        );
+    }
+}
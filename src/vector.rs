@@ -1,6 +1,7 @@
 use crate::axis::*;
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+#[repr(C)]
 pub struct Vector3<T> {
     pub x: T,
     pub y: T,
@@ -49,6 +50,31 @@ where
     }
 }
 
+impl<T> Vector3<T>
+where
+    T: num_traits::Float,
+{
+    pub fn dot(self, other: Self) -> T {
+        self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    pub fn cross(self, other: Self) -> Self {
+        Self {
+            x: self.y * other.z - self.z * other.y,
+            y: self.z * other.x - self.x * other.z,
+            z: self.x * other.y - self.y * other.x,
+        }
+    }
+
+    pub fn magnitude2(self) -> T {
+        self.dot(self)
+    }
+
+    pub fn magnitude(self) -> T {
+        self.magnitude2().sqrt()
+    }
+}
+
 impl<T> From<[T; 3]> for Vector3<T>
 where
     T: Copy,